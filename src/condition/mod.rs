@@ -6,7 +6,7 @@ use core::ops::RangeBounds;
 
 use ustr::Ustr;
 
-use crate::prelude::*;
+use crate::{plan::reservation::ReservationKey, prelude::*};
 
 pub mod relationship;
 
@@ -16,7 +16,11 @@ pub mod relationship;
 #[reflect(Component)]
 pub struct Condition {
     #[reflect(ignore, default = "Condition::true_pred")]
-    predicate: Arc<dyn Fn(&mut Props) -> bool + Send + Sync + 'static>,
+    predicate: Arc<dyn Fn(&Props) -> bool + Send + Sync + 'static>,
+    #[reflect(ignore)]
+    reads: Option<Arc<[Ustr]>>,
+    #[reflect(ignore)]
+    reservation: Option<ReservationKey>,
 }
 
 impl PartialEq for Condition {
@@ -28,18 +32,57 @@ impl Eq for Condition {}
 
 impl Condition {
     /// Creates a new condition with the given predicate.
+    /// The predicate works on a copy of the props, so anything it inserts is discarded.
+    /// Prefer [`Condition::read_only`], which doesn't need to copy them.
+    /// The props it reads are unknown, see [`Condition::with_reads`].
     pub fn new(predicate: impl Fn(&mut Props) -> bool + Send + Sync + 'static) -> Self {
+        Self::read_only(move |props| predicate(&mut props.clone()))
+    }
+
+    /// Creates a new condition with a predicate that only reads the props.
+    /// Props that are not present should be treated as their default value.
+    /// The props it reads are unknown, see [`Condition::with_reads`].
+    pub fn read_only(predicate: impl Fn(&Props) -> bool + Send + Sync + 'static) -> Self {
         Self {
             predicate: Arc::new(predicate),
+            reads: None,
+            reservation: None,
         }
     }
 
+    /// Declares the only props the predicate reads. Used by the [`PlanCache`](crate::plan::cache::PlanCache)
+    /// to decide which props a plan depends on. The shorthand constructors like [`Condition::eq`] already declare them.
+    pub fn with_reads(mut self, reads: impl IntoIterator<Item = impl Into<Ustr>>) -> Self {
+        self.reads = Some(reads.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The props the predicate reads, or `None` if they are unknown.
+    pub fn reads(&self) -> Option<&[Ustr]> {
+        self.reads.as_deref()
+    }
+
     /// Evaluates the condition with the given properties, returning whether it is fulfilled.
-    /// It will insert props holding default values if they are queried, but are not yet present in [`Props`].
-    pub fn is_fullfilled(&self, props: &mut Props) -> bool {
+    /// Props that are not present in [`Props`] are read as their default value.
+    /// Conditions created with [`Condition::not_reserved`] don't depend on the props and always hold here,
+    /// as only the planner knows the [`Reservations`](crate::plan::reservation::Reservations) of an agent.
+    pub fn is_fullfilled(&self, props: &Props) -> bool {
         (self.predicate)(props)
     }
 
+    /// Evaluates the condition like [`Condition::is_fullfilled`], but checks conditions created with
+    /// [`Condition::not_reserved`] with `is_reserved_by_other`.
+    pub(crate) fn is_fullfilled_with(
+        &self,
+        props: &Props,
+        is_reserved_by_other: impl FnOnce(ReservationKey) -> bool,
+    ) -> bool {
+        match self.reservation {
+            Some(key) => !is_reserved_by_other(key),
+            None => self.is_fullfilled(props),
+        }
+    }
+
     /// Shorthand for creating a condition for the concept of `props[name] == value`
     pub fn eq(name: impl Into<Ustr>, value: impl Into<Value>) -> Self {
         Self::cmp(name, value, |a, b| a == b)
@@ -76,17 +119,35 @@ impl Condition {
         range: impl RangeBounds<f32> + Send + Sync + 'static,
     ) -> Self {
         let name = name.into();
-        Self::new(move |props| range.contains(props.get_mut::<f32>(name)))
+        Self::read_only(move |props| match prop_or_default(props, name) {
+            Value::Float(value) => range.contains(&value),
+            _ => range.contains(&0.0),
+        })
+        .with_reads([name])
+    }
+
+    /// Shorthand for creating a condition that holds unless `key` is reserved by another agent.
+    /// See [`Reserve`](crate::plan::reservation::Reserve).
+    pub fn not_reserved(key: impl Into<ReservationKey>) -> Self {
+        Self {
+            reservation: Some(key.into()),
+            ..Self::always_true()
+        }
+    }
+
+    /// The key checked by a condition created with [`Condition::not_reserved`].
+    pub(crate) fn reservation(&self) -> Option<ReservationKey> {
+        self.reservation
     }
 
     /// Shorthand for creating a condition that always evaluates to true
     pub fn always_true() -> Self {
-        Self::new(|_| true)
+        Self::read_only(|_| true).with_reads([] as [Ustr; 0])
     }
 
     /// Shorthand for creating a condition that always evaluates to false
     pub fn always_false() -> Self {
-        Self::new(|_| false)
+        Self::read_only(|_| false).with_reads([] as [Ustr; 0])
     }
 
     /// Shortcut for creating a condition that compares a property with a value.
@@ -97,14 +158,23 @@ impl Condition {
     ) -> Self {
         let name = name.into();
         let value = value.into();
-        Self::new(move |p: &mut Props| predicate(*p.entry(name).or_default(), value))
+        Self::read_only(move |props| predicate(prop_or_default(props, name), value))
+            .with_reads([name])
     }
 
-    fn true_pred() -> Arc<dyn Fn(&mut Props) -> bool + Send + Sync + 'static> {
+    fn true_pred() -> Arc<dyn Fn(&Props) -> bool + Send + Sync + 'static> {
         Arc::new(|_| true)
     }
 }
 
+/// Reads a prop without inserting it, falling back to the default [`Value`].
+pub(crate) fn prop_or_default(props: &Props, name: Ustr) -> Value {
+    props
+        .iter()
+        .find_map(|(key, value)| (*key == name).then_some(*value))
+        .unwrap_or_default()
+}
+
 impl Debug for Condition {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Condition")
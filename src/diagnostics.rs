@@ -0,0 +1,174 @@
+//! Contains [`BaeDiagnosticsPlugin`] for measuring how much time is spent planning and executing plans.
+
+use core::time::Duration;
+
+use bevy_diagnostic::{
+    DEFAULT_MAX_HISTORY_LENGTH, Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic,
+};
+use bevy_ecs::{intern::Interned, schedule::ScheduleLabel};
+
+use crate::prelude::*;
+
+/// Adds diagnostics about the planner to the [`DiagnosticsStore`](bevy_diagnostic::DiagnosticsStore).
+/// All measurements are taken once per run of the schedule [`BaePlugin`] runs in, so the schedules should match.
+/// Use [`PlanDiagnostics`] for counters of individual agents.
+pub struct BaeDiagnosticsPlugin {
+    schedule: Interned<dyn ScheduleLabel>,
+    /// The total number of values to keep for averaging.
+    pub max_history_length: usize,
+}
+
+impl BaeDiagnosticsPlugin {
+    /// Number of [`UpdatePlan`]s processed per tick.
+    pub const REPLANS: DiagnosticPath = DiagnosticPath::const_new("bae/replans");
+    /// Time spent decomposing compound tasks per tick, in milliseconds.
+    pub const DECOMPOSITION_TIME: DiagnosticPath =
+        DiagnosticPath::const_new("bae/decomposition_time");
+    /// Average number of tasks visited per decomposition in a tick.
+    pub const TASKS_VISITED: DiagnosticPath = DiagnosticPath::const_new("bae/tasks_visited");
+    /// Number of [`Operator`]s run per tick.
+    pub const OPERATORS_EXECUTED: DiagnosticPath =
+        DiagnosticPath::const_new("bae/operators_executed");
+    /// Time spent executing plans per tick, in milliseconds. This includes the time spent in [`Operator`]s.
+    pub const EXECUTION_TIME: DiagnosticPath = DiagnosticPath::const_new("bae/execution_time");
+
+    /// Create a new plugin measuring the given schedule. The default is [`FixedUpdate`].
+    pub fn new(schedule: impl ScheduleLabel) -> Self {
+        Self {
+            schedule: schedule.intern(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for BaeDiagnosticsPlugin {
+    fn default() -> Self {
+        Self {
+            schedule: FixedUpdate.intern(),
+            max_history_length: DEFAULT_MAX_HISTORY_LENGTH,
+        }
+    }
+}
+
+impl Plugin for BaeDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let diagnostic =
+            |path| Diagnostic::new(path).with_max_history_length(self.max_history_length);
+        app.register_diagnostic(diagnostic(Self::REPLANS))
+            .register_diagnostic(diagnostic(Self::DECOMPOSITION_TIME).with_suffix("ms"))
+            .register_diagnostic(diagnostic(Self::TASKS_VISITED))
+            .register_diagnostic(diagnostic(Self::OPERATORS_EXECUTED))
+            .register_diagnostic(diagnostic(Self::EXECUTION_TIME).with_suffix("ms"))
+            .init_resource::<DiagnosticCounters>()
+            .add_systems(
+                self.schedule,
+                publish_diagnostics.after(BaeSystems::ExecutePlan),
+            );
+    }
+}
+
+/// Opt-in counters for a single agent. Insert this next to a [`Plan`] to start counting.
+#[derive(Component, Clone, Debug, Default, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct PlanDiagnostics {
+    /// Number of [`UpdatePlan`]s processed for this agent.
+    pub replans: u64,
+    /// Number of [`Operator`]s run for this agent.
+    pub operators_executed: u64,
+    /// Number of tasks visited by the last decomposition.
+    pub last_tasks_visited: u32,
+    /// Time spent by the last decomposition.
+    pub last_decomposition_time: Duration,
+}
+
+/// Accumulates measurements until they are published at the end of the tick.
+/// Only present when [`BaeDiagnosticsPlugin`] was added.
+#[derive(Resource, Default)]
+struct DiagnosticCounters {
+    replans: u32,
+    decomposition_time: Duration,
+    tasks_visited: u32,
+    operators_executed: u32,
+    execution_time: Duration,
+}
+
+/// Number of tasks visited by the decomposition that is currently running.
+#[derive(Resource, Default)]
+pub(crate) struct TasksVisited(pub(crate) u32);
+
+fn publish_diagnostics(mut diagnostics: Diagnostics, mut counters: ResMut<DiagnosticCounters>) {
+    let counters = core::mem::take(&mut *counters);
+    diagnostics.add_measurement(&BaeDiagnosticsPlugin::REPLANS, || counters.replans as f64);
+    diagnostics.add_measurement(&BaeDiagnosticsPlugin::DECOMPOSITION_TIME, || {
+        counters.decomposition_time.as_secs_f64() * 1000.0
+    });
+    diagnostics.add_measurement(&BaeDiagnosticsPlugin::TASKS_VISITED, || {
+        if counters.replans == 0 {
+            0.0
+        } else {
+            counters.tasks_visited as f64 / counters.replans as f64
+        }
+    });
+    diagnostics.add_measurement(&BaeDiagnosticsPlugin::OPERATORS_EXECUTED, || {
+        counters.operators_executed as f64
+    });
+    diagnostics.add_measurement(&BaeDiagnosticsPlugin::EXECUTION_TIME, || {
+        counters.execution_time.as_secs_f64() * 1000.0
+    });
+}
+
+/// Counts a task towards the decomposition that is currently running.
+/// Called by the builtin [`CompoundTask`]s for every subtask they look at.
+pub(crate) fn count_visited_task(world: &mut World) {
+    if let Some(mut visited) = world.get_resource_mut::<TasksVisited>() {
+        visited.0 += 1;
+    }
+}
+
+/// Resets the count of visited tasks before a new decomposition starts.
+pub(crate) fn reset_visited_tasks(world: &mut World) {
+    if let Some(mut visited) = world.get_resource_mut::<TasksVisited>() {
+        visited.0 = 0;
+    }
+}
+
+/// Sets the count of visited tasks for a decomposition that counted them elsewhere, e.g. on another thread.
+pub(crate) fn set_visited_tasks(world: &mut World, count: u32) {
+    if let Some(mut visited) = world.get_resource_mut::<TasksVisited>() {
+        visited.0 = count;
+    }
+}
+
+/// Records a replan of `entity` that took `duration` to decompose.
+pub(crate) fn record_replan(world: &mut World, entity: Entity, duration: Duration) {
+    let tasks_visited = world
+        .get_resource::<TasksVisited>()
+        .map_or(0, |visited| visited.0);
+    if let Some(mut counters) = world.get_resource_mut::<DiagnosticCounters>() {
+        counters.replans += 1;
+        counters.decomposition_time += duration;
+        counters.tasks_visited += tasks_visited;
+    }
+    if let Some(mut agent) = world.get_mut::<PlanDiagnostics>(entity) {
+        agent.replans += 1;
+        agent.last_tasks_visited = tasks_visited;
+        agent.last_decomposition_time = duration;
+    }
+}
+
+/// Records that an [`Operator`] of `entity` was run.
+pub(crate) fn record_operator_executed(world: &mut World, entity: Entity) {
+    if let Some(mut counters) = world.get_resource_mut::<DiagnosticCounters>() {
+        counters.operators_executed += 1;
+    }
+    if let Some(mut agent) = world.get_mut::<PlanDiagnostics>(entity) {
+        agent.operators_executed += 1;
+    }
+}
+
+/// Records the time a full run of plan execution took.
+pub(crate) fn record_execution_time(world: &mut World, duration: Duration) {
+    if let Some(mut counters) = world.get_resource_mut::<DiagnosticCounters>() {
+        counters.execution_time += duration;
+    }
+}
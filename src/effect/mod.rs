@@ -14,6 +14,8 @@ pub mod relationship;
 pub struct Effect {
     #[reflect(ignore, default = "Effect::noop")]
     effect: Arc<dyn Fn(&mut Props) + Send + Sync + 'static>,
+    #[reflect(ignore)]
+    reads: Option<Arc<[Ustr]>>,
     /// Whether the effect should be taken into account only during planning, but not applied for you.
     /// Default is `false`, i.e. all effects are applied when the associated step of the plan succeeds.
     pub plan_only: bool,
@@ -29,13 +31,28 @@ impl Eq for Effect {}
 
 impl Effect {
     /// Creates a new effect from the given function.
+    /// The props it reads are unknown, see [`Effect::with_reads`].
     pub fn new(fun: impl Fn(&mut Props) + Send + Sync + 'static) -> Self {
         Self {
             effect: Arc::new(fun),
+            reads: None,
             plan_only: false,
         }
     }
 
+    /// Declares the only props the effect reads. Props that are only written don't count.
+    /// Used by the [`PlanCache`](crate::plan::cache::PlanCache) to decide which props a plan depends on.
+    /// The shorthand constructors like [`Effect::set`] already declare them.
+    pub fn with_reads(mut self, reads: impl IntoIterator<Item = impl Into<Ustr>>) -> Self {
+        self.reads = Some(reads.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The props the effect reads, or `None` if they are unknown.
+    pub fn reads(&self) -> Option<&[Ustr]> {
+        self.reads.as_deref()
+    }
+
     /// Ensures that the effect is taken into account for planning, but not applied for you.
     /// This is useful for effects that come from the outside world, such as "did the monster find the player?".
     /// This is off by default, i.e. all effects are applied when the associated step of the plan succeeds.
@@ -53,7 +70,7 @@ impl Effect {
     pub fn set(name: impl Into<Ustr>, value: impl Into<Value>) -> Self {
         let name = name.into();
         let value = value.into();
-        Self::new(move |props| props.set(name, value))
+        Self::new(move |props| props.set(name, value)).with_reads([] as [Ustr; 0])
     }
 
     /// Shortcut for creating an effect that toggles a boolean property.
@@ -65,6 +82,7 @@ impl Effect {
             let val = props.get_mut::<bool>(name);
             *val = !*val;
         })
+        .with_reads([name])
     }
 
     /// Shortcut for creating an effect that increments a numeric property.
@@ -106,6 +124,7 @@ impl Effect {
             let prop = props.entry(name).or_default();
             mutate(prop, value);
         })
+        .with_reads([name])
     }
 
     /// Shortcut for creating an effect that does nothing. This is equivalent to just not spawning an effect at all.
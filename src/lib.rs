@@ -15,15 +15,39 @@ pub mod prelude {
             Effect,
             relationship::{EffectOf, EffectSpawner, EffectSpawnerCommands, Effects, effects},
         },
-        plan::{LogPlan, Plan, update::UpdatePlan},
+        plan::{
+            LogPlan, Plan,
+            async_planning::AsyncPlanning,
+            cache::PlanCache,
+            history::PlanHistory,
+            layer::{
+                PlanLayerOf, PlanLayerSpawner, PlanLayerSpawnerCommands, PlanLayers, plan_layers,
+            },
+            pause::{GlobalPlanPause, PlanPaused, StepPlan},
+            policy::ReplanPolicy,
+            repair::PlanRepair,
+            reservation::{ReservationKey, Reservations, Reserve},
+            scheduler::{ReplanBudget, ReplanPriority, ReplanScheduler},
+            scripted::{
+                PlanOverride, PlanOverrideEndReason, PlanOverrideEnded, PlanOverrideStarted,
+            },
+            target::PlanTarget,
+            tick_rate::{PlanTickRate, TickRate},
+            update::UpdatePlan,
+        },
         task::{
             OperatorStatus,
             compound::{
-                CompoundTask,
+                CompoundTask, MaxRecursionDepth,
+                reference::TaskRef,
                 relationship::{TaskOf, TaskSpawner, TaskSpawnerCommands, Tasks, tasks},
+                repeat::{MaxRepetitions, Repeat},
                 select::Select,
                 sequence::Sequence,
+                slot::{Slot, SlotCommandsExt},
+                smart_object::{SmartObject, SmartObjectSlot},
             },
+            delegate::Delegate,
             operator::{Operator, OperatorInput},
         },
     };
@@ -33,7 +57,7 @@ pub mod prelude {
         bevy_derive::{Deref, DerefMut},
         bevy_ecs::prelude::*,
         bevy_reflect::prelude::*,
-        tracing::{self, debug, info},
+        tracing::{self, debug, info, warn},
     };
 }
 extern crate alloc;
@@ -42,23 +66,45 @@ pub use bevy_mod_props;
 pub use bevy_mod_props::Ustr;
 
 use crate::{
+    diagnostics::TasksVisited,
     plan::{
+        async_planning::apply_async_plans,
+        cache::{invalidate_plan_cache_on_insert, invalidate_plan_cache_on_replace},
         execution::{execute_plan, update_empty_plans},
         log_plan,
+        pause::{advance_plan_steps, step_plan},
+        policy::track_policy_plans,
+        reservation::release_reservations,
+        scheduler::process_replan_queue,
+        scripted::{cancel_plan_override, start_plan_override},
+        tick_rate::tick_plan_rates,
         update::update_plan,
     },
     prelude::*,
     task::{
-        compound::CompoundAppExt,
+        batched::BaeSchedule,
+        compound::{
+            CompoundAppExt,
+            slot::{SlotUsers, release_slot_users, track_slot_users},
+        },
+        delegate::cancel_delegation,
+        operator::OperatorSystems,
         validation::{insert_bae_task_present_on_add, remove_bae_task_present_on_remove},
     },
 };
 
 pub mod condition;
+pub mod diagnostics;
 pub mod effect;
 mod name_ext;
 pub mod plan;
+#[cfg(feature = "record")]
+pub mod record;
+#[cfg(feature = "remote")]
+pub mod remote;
 pub mod task;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 /// The plugin required to use `bevy_bae`. The schedule used can be configured with [`Self::new`], and the default is [`FixedUpdate`].
 pub struct BaePlugin {
@@ -83,19 +129,49 @@ impl Default for BaePlugin {
 }
 impl Plugin for BaePlugin {
     fn build(&self, app: &mut App) {
-        app.configure_sets(self.schedule, (BaeSystems::ExecutePlan,).chain());
+        app.configure_sets(
+            self.schedule,
+            (BaeSystems::UpdateTickRate, BaeSystems::ExecutePlan).chain(),
+        );
+        app.insert_resource(BaeSchedule(self.schedule));
         app.world_mut().register_component::<Condition>();
         app.world_mut().register_component::<Effect>();
+        app.init_resource::<TasksVisited>()
+            .init_resource::<OperatorSystems>()
+            .init_resource::<Reservations>()
+            .init_resource::<SlotUsers>();
         app.add_observer(insert_bae_task_present_on_add::<Operator>)
             .add_observer(remove_bae_task_present_on_remove::<Operator>)
             .add_observer(insert_bae_task_present_on_add::<Tasks>)
             .add_observer(remove_bae_task_present_on_remove::<Tasks>);
         app.add_compound_task::<Select>()
-            .add_compound_task::<Sequence>();
-        app.add_observer(update_plan).add_observer(log_plan);
+            .add_compound_task::<Sequence>()
+            .add_compound_task::<SmartObjectSlot>()
+            .add_compound_task::<Slot>()
+            .add_compound_task::<TaskRef>()
+            .add_compound_task::<Repeat>();
+        app.add_observer(update_plan)
+            .add_observer(log_plan)
+            .add_observer(step_plan)
+            .add_observer(start_plan_override)
+            .add_observer(cancel_plan_override)
+            .add_observer(track_policy_plans)
+            .add_observer(release_reservations)
+            .add_observer(cancel_delegation)
+            .add_observer(track_slot_users)
+            .add_observer(release_slot_users)
+            .add_observer(invalidate_plan_cache_on_insert)
+            .add_observer(invalidate_plan_cache_on_replace);
         app.add_systems(
             self.schedule,
-            ((update_empty_plans, execute_plan)
+            ((
+                tick_plan_rates,
+                advance_plan_steps,
+                apply_async_plans,
+                update_empty_plans,
+                process_replan_queue,
+                execute_plan,
+            )
                 .chain()
                 .in_set(BaeSystems::ExecutePlan),),
         );
@@ -105,6 +181,8 @@ impl Plugin for BaePlugin {
 /// System set used by all systems of `bevy_bae`.
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum BaeSystems {
+    /// Runs before [`BaeSystems::ExecutePlan`]. Add systems here that change [`PlanTickRate`]s.
+    UpdateTickRate,
     /// Executes [`Plan`]s, and replans them if necessary.
     ExecutePlan,
 }
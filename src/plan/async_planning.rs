@@ -0,0 +1,373 @@
+//! Contains [`AsyncPlanning`] for decomposing plans on the [`AsyncComputeTaskPool`].
+
+use bevy_mod_props::PropsExt;
+use bevy_platform::{collections::HashMap, time::Instant};
+use bevy_tasks::{AsyncComputeTaskPool, Task, block_on, futures::check_ready};
+use core::time::Duration;
+use ustr::Ustr;
+
+use crate::{
+    condition::prop_or_default,
+    diagnostics::{record_replan, set_visited_tasks},
+    plan::{
+        history::{PlanEndReason, record_plan_ended},
+        mtr::Mtr,
+        reservation::{ReservationKey, is_key_reserved_by_other},
+        target::props_source,
+        update::{apply_decompose_result, previous_mtr, update_plan_inner},
+    },
+    prelude::*,
+    task::{
+        compound::{
+            DecomposeInput, DecomposeResult, TaskSource, WorldState, select::select,
+            sequence::sequence,
+        },
+        delegate::end_delegation,
+    },
+};
+
+/// Insert this next to a [`Plan`] to decompose its domain on the [`AsyncComputeTaskPool`] instead of blocking the schedule.
+///
+/// When an [`UpdatePlan`] is triggered, the [`Props`] of the agent and its domain are snapshotted and decomposed in the background.
+/// The resulting [`Plan`] is applied in the first [`BaeSystems::ExecutePlan`] after it is ready.
+/// If one of the [`Props`] read by the [`Condition`]s and [`Effect`]s of the domain changed in the meantime,
+/// the result is discarded and a new [`UpdatePlan`] is triggered.
+/// [`UpdatePlan`]s triggered while a decomposition is pending are ignored. Use [`finish_async_plans`] to wait for pending decompositions.
+///
+/// Only [`Select`], [`Sequence`] and [`Operator`]s can be snapshotted, as they don't need world access to decompose.
+/// Domains using other [`CompoundTask`]s are planned synchronously as usual.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+#[require(Plan)]
+pub struct AsyncPlanning;
+
+/// A decomposition running in the background for an agent with [`AsyncPlanning`].
+#[derive(Component)]
+pub(crate) struct PendingPlan {
+    task: Task<AsyncOutcome>,
+    /// The props the decomposition started with.
+    snapshot: WorldState,
+    /// The props the domain reads, or `None` if some of them are unknown.
+    reads: Option<Vec<Ustr>>,
+    /// The keys checked by [`Condition::not_reserved`] in the domain, and whether another agent held them.
+    reservations: Vec<(ReservationKey, bool)>,
+    previous_mtr: Mtr,
+}
+
+enum AsyncOutcome {
+    ConditionFailed(Entity),
+    Decomposed {
+        result: DecomposeResult,
+        duration: Duration,
+        tasks_visited: u32,
+    },
+}
+
+/// Plain data copy of the part of a domain that can be decomposed without world access, so it can be sent to another thread.
+struct DomainSnapshot {
+    root: Entity,
+    tasks: HashMap<Entity, TaskSnapshot>,
+    /// The keys checked by [`Condition::not_reserved`] in the domain that another agent held.
+    reserved: Vec<ReservationKey>,
+    tasks_visited: u32,
+}
+
+struct TaskSnapshot {
+    kind: SnapshotKind,
+    subtasks: Vec<Entity>,
+    conditions: Vec<(Entity, Condition)>,
+    effects: Vec<(Entity, Effect)>,
+    /// Whether the task reserves something held by another agent.
+    reserved: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SnapshotKind {
+    Operator,
+    Select,
+    Sequence,
+}
+
+/// Called instead of [`update_plan_inner`] for agents with [`AsyncPlanning`].
+pub(crate) fn start_async_plan(update: In<UpdatePlan>, world: &mut World) -> Result {
+    let root = update.entity;
+    if world.entity(root).contains::<PendingPlan>() || world.entity(root).contains::<PlanOverride>()
+    {
+        return Ok(());
+    }
+    let source = props_source(world, root);
+    let Some(domain) = DomainSnapshot::new(world, root) else {
+        return world.run_system_cached_with(update_plan_inner, UpdatePlan::new(root))?;
+    };
+
+    #[cfg(feature = "record")]
+    crate::record::record_replan(world, root);
+    let snapshot = WorldState::new(world.entity(source).props().clone());
+    let reads = domain.reads();
+    let reservations = domain
+        .reservation_keys()
+        .into_iter()
+        .map(|key| (key, domain.reserved.contains(&key)))
+        .collect();
+    let previous_mtr = previous_mtr(world, root);
+    let task = AsyncComputeTaskPool::get().spawn({
+        let world_state = snapshot.clone();
+        let previous_mtr = previous_mtr.clone();
+        async move { plan_domain(domain, world_state, previous_mtr) }
+    });
+    world.entity_mut(root).insert(PendingPlan {
+        task,
+        snapshot,
+        reads,
+        reservations,
+        previous_mtr,
+    });
+    Ok(())
+}
+
+/// Applies the results of all finished background decompositions.
+pub(crate) fn apply_async_plans(
+    world: &mut World,
+    mut pending: Local<QueryState<(Entity, &mut PendingPlan)>>,
+    mut ready: Local<Vec<(Entity, AsyncOutcome)>>,
+) {
+    ready.extend(pending.iter_mut(world).filter_map(|(entity, mut pending)| {
+        let outcome = check_ready(&mut pending.task)?;
+        Some((entity, outcome))
+    }));
+    for (root, outcome) in ready.drain(..) {
+        apply_async_outcome(world, root, outcome);
+    }
+}
+
+/// Blocks until all background decompositions are finished and applies their results right away,
+/// e.g. to make tests deterministic or to have all agents planned before a loading screen ends.
+/// The decompositions must be able to progress on other threads, which requires a multi-threaded [`AsyncComputeTaskPool`].
+pub fn finish_async_plans(world: &mut World) {
+    let mut pending = world.query::<(Entity, &mut PendingPlan)>();
+    let ready: Vec<_> = pending
+        .iter_mut(world)
+        .map(|(entity, mut pending)| (entity, block_on(&mut pending.task)))
+        .collect();
+    for (root, outcome) in ready {
+        apply_async_outcome(world, root, outcome);
+    }
+}
+
+fn apply_async_outcome(world: &mut World, root: Entity, outcome: AsyncOutcome) {
+    let Some(pending) = world.entity_mut(root).take::<PendingPlan>() else {
+        return;
+    };
+    let source = props_source(world, root);
+    let changed = world
+        .get::<Props>(source)
+        .is_some_and(|props| props_changed(&pending.snapshot, props, pending.reads.as_deref()))
+        || pending
+            .reservations
+            .iter()
+            .any(|&(key, reserved)| is_key_reserved_by_other(world, key, root) != reserved);
+    if changed {
+        debug!(
+            entity = ?root,
+            "props changed during async planning, discarding result"
+        );
+        world.trigger(UpdatePlan::new(root));
+        return;
+    }
+    match outcome {
+        AsyncOutcome::ConditionFailed(condition) => {
+            record_plan_ended(world, root, PlanEndReason::ConditionFailed { condition });
+            end_delegation(world, root, false);
+            world.entity_mut(root).insert(Plan::default());
+        }
+        AsyncOutcome::Decomposed {
+            result,
+            duration,
+            tasks_visited,
+        } => {
+            set_visited_tasks(world, tasks_visited);
+            record_replan(world, root, duration);
+            apply_decompose_result(world, root, &pending.previous_mtr, result);
+        }
+    }
+}
+
+/// Returns whether any of the props in `reads` differ between `snapshot` and `current`.
+/// If `reads` is `None`, all props are compared.
+fn props_changed(snapshot: &Props, current: &Props, reads: Option<&[Ustr]>) -> bool {
+    let changed = |&name: &Ustr| prop_or_default(snapshot, name) != prop_or_default(current, name);
+    match reads {
+        Some(reads) => reads.iter().any(changed),
+        None => snapshot
+            .iter()
+            .chain(current.iter())
+            .any(|(name, _)| changed(name)),
+    }
+}
+
+impl DomainSnapshot {
+    /// Copies the domain of `planner`.
+    /// Returns `None` if it contains a [`CompoundTask`] other than [`Select`] and [`Sequence`].
+    fn new(world: &World, planner: Entity) -> Option<Self> {
+        let mut tasks = HashMap::default();
+        let mut stack = vec![planner];
+        while let Some(task) = stack.pop() {
+            let entity = world.get_entity(task).ok()?;
+            let kind = if entity.contains::<Operator>() {
+                SnapshotKind::Operator
+            } else if entity.contains::<Select>() {
+                SnapshotKind::Select
+            } else if entity.contains::<Sequence>() {
+                SnapshotKind::Sequence
+            } else {
+                return None;
+            };
+            let subtasks = world.subtasks(task);
+            stack.extend(&subtasks);
+            let snapshot = TaskSnapshot {
+                kind,
+                subtasks,
+                conditions: world.conditions(task),
+                effects: world.effects(task),
+                reserved: world.is_reserved_by_other(task, planner),
+            };
+            tasks.insert(task, snapshot);
+        }
+        let mut domain = Self {
+            root: planner,
+            tasks,
+            reserved: Vec::new(),
+            tasks_visited: 0,
+        };
+        domain.reserved = domain
+            .reservation_keys()
+            .into_iter()
+            .filter(|&key| is_key_reserved_by_other(world, key, planner))
+            .collect();
+        Some(domain)
+    }
+
+    /// The keys checked by the [`Condition::not_reserved`]s of the domain.
+    fn reservation_keys(&self) -> Vec<ReservationKey> {
+        let mut keys = Vec::new();
+        for (_, condition) in self.tasks.values().flat_map(|task| &task.conditions) {
+            if let Some(key) = condition.reservation()
+                && !keys.contains(&key)
+            {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// The props read by the conditions and effects of the domain, or `None` if some of them are unknown.
+    fn reads(&self) -> Option<Vec<Ustr>> {
+        let mut keys = Vec::new();
+        for task in self.tasks.values() {
+            let conditions = task
+                .conditions
+                .iter()
+                .map(|(_, condition)| condition.reads());
+            let effects = task.effects.iter().map(|(_, effect)| effect.reads());
+            for reads in conditions.chain(effects) {
+                keys.extend_from_slice(reads?);
+            }
+        }
+        keys.sort();
+        keys.dedup();
+        Some(keys)
+    }
+
+    fn kind(&self, task: Entity) -> Option<SnapshotKind> {
+        self.tasks.get(&task).map(|task| task.kind)
+    }
+}
+
+impl TaskSource for DomainSnapshot {
+    fn subtasks(&self, task: Entity) -> Vec<Entity> {
+        self.tasks
+            .get(&task)
+            .map(|task| task.subtasks.clone())
+            .unwrap_or_default()
+    }
+
+    fn is_operator(&self, task: Entity) -> bool {
+        self.kind(task) == Some(SnapshotKind::Operator)
+    }
+
+    fn conditions(&self, task: Entity) -> Vec<(Entity, Condition)> {
+        self.tasks
+            .get(&task)
+            .map(|task| task.conditions.clone())
+            .unwrap_or_default()
+    }
+
+    fn effects(&self, task: Entity) -> Vec<(Entity, Effect)> {
+        self.tasks
+            .get(&task)
+            .map(|task| task.effects.clone())
+            .unwrap_or_default()
+    }
+
+    fn is_reserved_by_other(&self, task: Entity, _planner: Entity) -> bool {
+        self.tasks.get(&task).is_some_and(|task| task.reserved)
+    }
+
+    fn is_key_reserved_by_other(&self, key: ReservationKey, _planner: Entity) -> bool {
+        self.reserved.contains(&key)
+    }
+
+    fn count_visited_task(&mut self) {
+        self.tasks_visited += 1;
+    }
+
+    fn decompose_compound(&mut self, input: DecomposeInput) -> DecomposeResult {
+        let task = input.compound_task;
+        let tasks = self.subtasks(task);
+        let mut result = match self.kind(task) {
+            Some(SnapshotKind::Select) => select(self, &tasks, input),
+            Some(SnapshotKind::Sequence) => sequence(self, &tasks, input),
+            Some(SnapshotKind::Operator) | None => DecomposeResult::Failure,
+        };
+        if let DecomposeResult::Success { sub_plan, .. } = &mut result {
+            sub_plan.enclose(task);
+        }
+        result
+    }
+}
+
+fn plan_domain(
+    mut domain: DomainSnapshot,
+    world_state: WorldState,
+    previous_mtr: Mtr,
+) -> AsyncOutcome {
+    let start = Instant::now();
+    let root = domain.root;
+    let mut conditions = Vec::new();
+    for (entity, condition) in domain.conditions(root) {
+        if !domain.condition_holds(&condition, &world_state, root) {
+            return AsyncOutcome::ConditionFailed(entity);
+        }
+        conditions.push(entity);
+    }
+    let result = if domain.is_operator(root) {
+        domain.count_visited_task();
+        DecomposeResult::Success {
+            sub_plan: Plan::single(root, conditions),
+            world_state,
+        }
+    } else {
+        domain.decompose_compound(DecomposeInput {
+            planner: root,
+            compound_task: root,
+            world_state,
+            previous_mtr,
+            conditions,
+        })
+    };
+    AsyncOutcome::Decomposed {
+        result,
+        duration: start.elapsed(),
+        tasks_visited: domain.tasks_visited,
+    }
+}
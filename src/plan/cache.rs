@@ -0,0 +1,354 @@
+//! Contains the [`PlanCache`] for reusing decompositions of identical [`Props`].
+
+use alloc::collections::VecDeque;
+use core::{
+    fmt::Write as _,
+    hash::{BuildHasher as _, Hash as _, Hasher},
+};
+
+use bevy_platform::{
+    collections::{HashMap, HashSet},
+    hash::FixedHasher,
+};
+
+use crate::{prelude::*, task::compound::domain_tasks};
+
+/// Opt-in cache of decomposition results. While this resource exists, decompositions of agents that replan
+/// from scratch, i.e. without a running [`Plan`], are cached. The cache is keyed by the domain root and
+/// the [`Props`] read by the [`Condition`]s and [`Effect`]s of the domain. On a hit, decomposition is skipped entirely.
+///
+/// Conditions and effects created with [`Condition::new`], [`Condition::read_only`] or [`Effect::new`] don't declare which props they read,
+/// see [`Condition::with_reads`] and [`Effect::with_reads`]. If a domain contains such a condition or effect, all props are hashed.
+///
+/// The entries of a domain are dropped when [`Tasks`], [`Conditions`], [`Effects`], [`Condition`]s or [`Effect`]s of the domain
+/// are inserted or removed, together with the entries of all domains referencing it through a [`TaskRef`]. Only use this cache for domains whose decomposition depends on nothing but [`Props`].
+/// Domains containing a [`SmartObjectSlot`], a [`Reserve`] or a [`Condition::not_reserved`] are never cached,
+/// as they depend on the [`SmartObject`]s around the agent and on the [`Reservations`] of other agents.
+#[derive(Resource, Debug)]
+pub struct PlanCache {
+    /// How many decompositions are kept at most. When the cache is full, the oldest entry is dropped.
+    pub capacity: usize,
+    entries: HashMap<(Entity, u64), CachedEntry>,
+    order: VecDeque<(Entity, u64)>,
+    domains: HashMap<Entity, CachedDomain>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for PlanCache {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedEntry {
+    /// The props the decomposition was cached for, as the hash alone may collide.
+    props: Vec<(Ustr, Value)>,
+    decomposition: CachedDecomposition,
+}
+
+#[derive(Debug, Clone)]
+enum CachedDecomposition {
+    Success(Plan),
+    Failure,
+}
+
+#[derive(Debug, Clone)]
+enum ReadSet {
+    All,
+    Keys(Vec<Ustr>),
+    /// The decomposition depends on more than [`Props`], e.g. on [`Reservations`], so it is never cached.
+    Uncacheable,
+}
+
+#[derive(Debug, Clone)]
+struct CachedDomain {
+    reads: ReadSet,
+    /// Roots of the domains reached through a [`TaskRef`].
+    references: Vec<Entity>,
+}
+
+/// Identifies a decomposition of a domain with specific [`Props`].
+pub(crate) struct PlanCacheKey {
+    hash: u64,
+    props: Vec<(Ustr, Value)>,
+}
+
+impl PlanCache {
+    /// Creates a new empty cache that keeps at most `capacity` decompositions.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::default(),
+            order: VecDeque::new(),
+            domains: HashMap::default(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the number of cached decompositions.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether no decompositions are cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns how often a cached decomposition was used.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns how often a decomposition was not found in the cache.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Drops all cached decompositions.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.domains.clear();
+    }
+
+    /// Drops all cached decompositions of the domain rooted at `root` and of all domains referencing it through a [`TaskRef`].
+    pub fn invalidate(&mut self, root: Entity) {
+        let mut visited = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(root) = stack.pop() {
+            if !visited.insert(root) {
+                continue;
+            }
+            stack.extend(
+                self.domains
+                    .iter()
+                    .filter(|(_, domain)| domain.references.contains(&root))
+                    .map(|(&referrer, _)| referrer),
+            );
+            if self.domains.remove(&root).is_none() {
+                continue;
+            }
+            self.entries.retain(|(entity, _), _| *entity != root);
+            self.order.retain(|(entity, _)| *entity != root);
+        }
+    }
+
+    fn insert(&mut self, key: (Entity, u64), entry: CachedEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key, entry).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Returns the cache key for decomposing the domain of `root` with `props`,
+/// or `None` if there is no [`PlanCache`] or the domain can't be cached.
+pub(crate) fn plan_cache_key(
+    world: &mut World,
+    root: Entity,
+    props: &Props,
+) -> Option<PlanCacheKey> {
+    if !world.contains_resource::<PlanCache>() {
+        return None;
+    }
+    let reads = match world.resource::<PlanCache>().domains.get(&root) {
+        Some(domain) => domain.reads.clone(),
+        None => {
+            let domain = collect_domain(world, root);
+            let reads = domain.reads.clone();
+            world
+                .resource_mut::<PlanCache>()
+                .domains
+                .insert(root, domain);
+            reads
+        }
+    };
+    if let ReadSet::Uncacheable = reads {
+        return None;
+    }
+    let mut props: Vec<_> = props
+        .iter()
+        .filter(|(name, _)| match &reads {
+            ReadSet::All | ReadSet::Uncacheable => true,
+            ReadSet::Keys(keys) => keys.binary_search(*name).is_ok(),
+        })
+        .map(|(&name, &value)| (name, value))
+        .collect();
+    props.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut hasher = HashWriter(FixedHasher.build_hasher());
+    for (name, value) in &props {
+        name.hash(&mut hasher.0);
+        // `Value` is not `Hash`, as it may contain floats
+        let _ = write!(hasher, "{value:?}");
+    }
+    Some(PlanCacheKey {
+        hash: hasher.0.finish(),
+        props,
+    })
+}
+
+/// Looks up a cached decomposition of the domain of `root`.
+pub(crate) fn cached_plan(
+    world: &mut World,
+    root: Entity,
+    key: &PlanCacheKey,
+) -> Option<Option<Plan>> {
+    let mut cache = world.get_resource_mut::<PlanCache>()?;
+    let Some(cached) = cache
+        .entries
+        .get(&(root, key.hash))
+        .filter(|cached| cached.props == key.props)
+        .map(|cached| cached.decomposition.clone())
+    else {
+        cache.misses += 1;
+        return None;
+    };
+    cache.hits += 1;
+    Some(match cached {
+        CachedDecomposition::Success(plan) => Some(plan),
+        CachedDecomposition::Failure => None,
+    })
+}
+
+/// Caches the decomposition of the domain of `root`. Pass `None` for a failed decomposition.
+pub(crate) fn cache_plan(world: &mut World, root: Entity, key: PlanCacheKey, plan: Option<&Plan>) {
+    if let Some(mut cache) = world.get_resource_mut::<PlanCache>() {
+        let decomposition = match plan {
+            Some(plan) => CachedDecomposition::Success(plan.clone()),
+            None => CachedDecomposition::Failure,
+        };
+        cache.insert(
+            (root, key.hash),
+            CachedEntry {
+                props: key.props,
+                decomposition,
+            },
+        );
+    }
+}
+
+struct HashWriter<H>(H);
+
+impl<H: Hasher> core::fmt::Write for HashWriter<H> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Collects the props read by all conditions and effects in the domain of `root`, including referenced subtrees,
+/// and the roots of the referenced subtrees.
+fn collect_domain(world: &World, root: Entity) -> CachedDomain {
+    let mut references = Vec::new();
+    let reads = collect_reads(world, root, &mut references);
+    CachedDomain { reads, references }
+}
+
+fn collect_reads(world: &World, root: Entity, references: &mut Vec<Entity>) -> ReadSet {
+    let mut keys = Vec::new();
+    let mut all = false;
+    for task in domain_tasks(world, root) {
+        let entity = world.entity(task);
+        if entity.contains::<SmartObjectSlot>() || entity.contains::<Reserve>() {
+            return ReadSet::Uncacheable;
+        }
+        if let Some(&TaskRef(mut target)) = entity.get::<TaskRef>() {
+            while let Some(parent) = world.get::<TaskOf>(target) {
+                target = parent.0;
+            }
+            references.push(target);
+        }
+        let loop_condition = match entity.get::<Repeat>() {
+            Some(Repeat::While(condition)) => Some(condition),
+            _ => None,
+        };
+        let conditions = entity
+            .get::<Conditions>()
+            .into_iter()
+            .flatten()
+            .filter_map(|condition| world.get::<Condition>(condition));
+        for condition in loop_condition.into_iter().chain(conditions) {
+            if condition.reservation().is_some() {
+                return ReadSet::Uncacheable;
+            }
+            match condition.reads() {
+                Some(reads) => keys.extend_from_slice(reads),
+                None => all = true,
+            }
+        }
+        let effects = entity
+            .get::<Effects>()
+            .into_iter()
+            .flatten()
+            .filter_map(|effect| world.get::<Effect>(effect));
+        for effect in effects {
+            match effect.reads() {
+                Some(reads) => keys.extend_from_slice(reads),
+                None => all = true,
+            }
+        }
+    }
+    if all {
+        return ReadSet::All;
+    }
+    keys.sort();
+    keys.dedup();
+    ReadSet::Keys(keys)
+}
+
+/// Drops the cached decompositions of a domain when one of its tasks, conditions or effects is added or removed.
+pub(crate) fn invalidate_plan_cache_on_insert(
+    insert: On<Insert, (TaskOf, TaskRef, ConditionOf, EffectOf, Condition, Effect)>,
+    cache: Option<ResMut<PlanCache>>,
+    task_of: Query<&TaskOf>,
+    condition_of: Query<&ConditionOf>,
+    effect_of: Query<&EffectOf>,
+) {
+    if let Some(mut cache) = cache {
+        let root = domain_root(insert.entity, &task_of, &condition_of, &effect_of);
+        cache.invalidate(root);
+    }
+}
+
+/// Drops the cached decompositions of a domain when one of its tasks, conditions or effects is removed.
+pub(crate) fn invalidate_plan_cache_on_replace(
+    replace: On<Replace, (TaskOf, TaskRef, ConditionOf, EffectOf, Condition, Effect)>,
+    cache: Option<ResMut<PlanCache>>,
+    task_of: Query<&TaskOf>,
+    condition_of: Query<&ConditionOf>,
+    effect_of: Query<&EffectOf>,
+) {
+    if let Some(mut cache) = cache {
+        let root = domain_root(replace.entity, &task_of, &condition_of, &effect_of);
+        cache.invalidate(root);
+    }
+}
+
+fn domain_root(
+    entity: Entity,
+    task_of: &Query<&TaskOf>,
+    condition_of: &Query<&ConditionOf>,
+    effect_of: &Query<&EffectOf>,
+) -> Entity {
+    let mut root = condition_of
+        .get(entity)
+        .map(|condition_of| condition_of.0)
+        .or_else(|_| effect_of.get(entity).map(|effect_of| effect_of.0))
+        .unwrap_or(entity);
+    while let Ok(parent) = task_of.get(root) {
+        root = parent.0;
+    }
+    root
+}
@@ -1,11 +1,54 @@
-use crate::{plan::TaskNode, prelude::*};
+use bevy_platform::time::Instant;
+use bevy_time::Time;
+use core::time::Duration;
+
+#[cfg(feature = "record")]
+use crate::record::{record_operator_status, replayed_status};
+use crate::{
+    diagnostics::{record_execution_time, record_operator_executed},
+    plan::{
+        TaskNode,
+        history::{PlanEndReason, record_operator_run, record_plan_ended},
+        layer::replan_other_layers,
+        pause::{GlobalPlanPause, PlanPaused, PlanSteps, is_running},
+        policy::{ReplanPolicyState, is_cooling_down, record_policy_plan_ended},
+        repair::{PlanRepair, repair_plan},
+        scripted::{PlanOverride, end_plan_override},
+        target::{actor, props_source},
+        tick_rate::PlanTickRate,
+    },
+    prelude::*,
+    task::{
+        batched::run_batched,
+        compound::{TaskSource, smart_object::smart_object_of},
+        delegate::{Delegation, end_delegation},
+    },
+};
 
 pub(crate) fn update_empty_plans(
-    mut plans: Query<(Entity, NameOrEntity, &Plan)>,
+    mut plans: Query<(
+        Entity,
+        NameOrEntity,
+        &Plan,
+        Option<&PlanTickRate>,
+        Option<&ReplanPolicyState>,
+        Has<PlanPaused>,
+        Option<&PlanSteps>,
+        Option<&Delegation>,
+    )>,
+    time: Option<Res<Time>>,
+    global_pause: Option<Res<GlobalPlanPause>>,
     mut commands: Commands,
 ) {
-    for (entity, name, plan) in plans.iter_mut() {
-        if plan.is_empty() {
+    let now = time.map(|time| time.elapsed()).unwrap_or_default();
+    for (entity, name, plan, tick_rate, policy_state, paused, steps, delegation) in plans.iter_mut()
+    {
+        if plan.is_empty()
+            && delegation.is_none_or(|delegation| !delegation.is_finished_task())
+            && tick_rate.is_none_or(PlanTickRate::is_due)
+            && is_running(paused, steps, global_pause.as_deref())
+            && !is_cooling_down(policy_state, now)
+        {
             commands.entity(entity).trigger(UpdatePlan::new);
             debug!(entity=?name.entity, name=?name.name, "Plan is empty, triggering replan.");
         }
@@ -14,32 +57,63 @@ pub(crate) fn update_empty_plans(
 
 pub(crate) fn execute_plan(
     world: &mut World,
-    mut plans: Local<QueryState<(NameOrEntity, &mut Plan)>>,
+    mut plans: Local<
+        QueryState<(
+            NameOrEntity,
+            &mut Plan,
+            Option<&PlanTickRate>,
+            Has<PlanPaused>,
+            Option<&PlanSteps>,
+        )>,
+    >,
     mut conditions: Local<QueryState<(NameOrEntity, &Condition)>>,
     mut operators: Local<QueryState<(NameOrEntity, &Operator)>>,
     mut effects: Local<QueryState<(NameOrEntity, &Effect)>>,
-    mut plans_scratch: Local<Vec<(Entity, Option<Name>, TaskNode)>>,
+    mut plans_scratch: Local<Vec<(Entity, Option<Name>, TaskNode, Duration)>>,
     mut condition_scratch: Local<Vec<(Entity, Option<Name>, Condition)>>,
     mut effects_scratch: Local<Vec<(Entity, Option<Name>, Effect)>>,
 ) {
-    plans_scratch.extend(plans.iter(world).filter_map(|(name, plan)| {
-        let idx = *plan.front()?;
-        Some((name.entity, name.name.cloned(), plan.nodes[idx].clone()))
-    }));
+    let start = Instant::now();
+    let delta = world
+        .get_resource::<Time>()
+        .map(Time::delta)
+        .unwrap_or_default();
+    let global_pause = world.get_resource::<GlobalPlanPause>();
+    plans_scratch.extend(
+        plans
+            .iter(world)
+            .filter_map(|(name, plan, tick_rate, paused, steps)| {
+                if !is_running(paused, steps, global_pause) {
+                    return None;
+                }
+                let delta = match tick_rate {
+                    Some(tick_rate) => tick_rate.due()?,
+                    None => delta,
+                };
+                let idx = *plan.front()?;
+                Some((
+                    name.entity,
+                    name.name.cloned(),
+                    plan.nodes[idx].clone(),
+                    delta,
+                ))
+            }),
+    );
 
-    for (plan_entity, plan_name, planned_operator) in plans_scratch.drain(..) {
+    for (plan_entity, plan_name, planned_operator, delta) in plans_scratch.drain(..) {
         debug!(?plan_entity, ?plan_name, "checking conditions");
-        let mut all_conditions_met = true;
+        let mut failed_condition = None;
         {
             condition_scratch.extend(
                 conditions
                     .iter_many(world, planned_operator.conditions.iter())
                     .map(|(name, condition)| (name.entity, name.name.cloned(), condition.clone())),
             );
-            let mut entity_mut = world.entity_mut(plan_entity);
-            let mut props = entity_mut.get_mut::<Props>().unwrap();
+            let source = props_source(world, plan_entity);
+            // Only read the props, so that evaluating conditions doesn't mark them as changed
+            let props = world.get::<Props>(source).unwrap();
             for (condition_entity, condition_name, condition) in condition_scratch.drain(..) {
-                if condition.is_fullfilled(&mut props) {
+                if world.condition_holds(&condition, props, plan_entity) {
                     debug!(
                         ?plan_entity,
                         ?plan_name,
@@ -55,15 +129,19 @@ pub(crate) fn execute_plan(
                         ?condition_name,
                         "encountered unsatisfied condition, aborting plan"
                     );
-                    all_conditions_met = false;
+                    failed_condition = Some(condition_entity);
                     break;
                 }
             }
         }
-        let result: Result<OperatorStatus, _> = if all_conditions_met {
+        let result: Result<OperatorStatus, _> = if failed_condition.is_none() {
             let input = OperatorInput {
                 entity: plan_entity,
+                actor: actor(world, plan_entity),
+                props: props_source(world, plan_entity),
+                smart_object: smart_object_of(world, planned_operator.entity),
                 operator: planned_operator.entity,
+                delta,
             };
             if let Ok((op_name, operator)) = operators.get(world, planned_operator.entity) {
                 debug!(
@@ -73,8 +151,31 @@ pub(crate) fn execute_plan(
                     operator_name=?op_name.name,
                     "running operator"
                 );
-                let result = world.run_system_with(operator.system_id(), input);
-                world.flush();
+                let system_id = operator.system_id();
+                let start_batched = operator.start_batched;
+                #[cfg(feature = "record")]
+                let replayed = replayed_status(world, plan_entity, planned_operator.entity);
+                #[cfg(not(feature = "record"))]
+                let replayed = None;
+                let result = match (replayed, start_batched) {
+                    (Some(status), _) => Ok(status),
+                    // Batched operators are run by regular systems, so only their status is picked up here
+                    (None, Some(start)) => {
+                        let status = run_batched(world, &input, start);
+                        world.flush();
+                        Ok(status)
+                    }
+                    (None, None) => {
+                        let result = world.run_system_with(system_id, input);
+                        world.flush();
+                        result
+                    }
+                };
+                let status = result.as_ref().copied().unwrap_or(OperatorStatus::Failure);
+                record_operator_executed(world, plan_entity);
+                record_operator_run(world, plan_entity, planned_operator.entity, status);
+                #[cfg(feature = "record")]
+                record_operator_status(world, plan_entity, planned_operator.entity, status);
                 result
             } else {
                 debug!(
@@ -87,7 +188,7 @@ pub(crate) fn execute_plan(
             Ok(OperatorStatus::Failure)
         };
 
-        let force_replan = match result {
+        let end_reason = match result {
             Ok(OperatorStatus::Success) => {
                 debug!(
                     ?plan_entity,
@@ -99,6 +200,7 @@ pub(crate) fn execute_plan(
                 let mut plan: Mut<Plan> = plan_mut.get_mut::<Plan>().unwrap();
 
                 let idx = plan.pop_front().unwrap();
+                plan.update_track();
 
                 let step = plan.nodes[idx].clone();
 
@@ -107,8 +209,10 @@ pub(crate) fn execute_plan(
                         .iter_many(world, step.effects.iter())
                         .map(|(name, effect)| (name.entity, name.name.cloned(), effect.clone())),
                 );
-                let mut entity = world.entity_mut(plan_entity);
+                let source = props_source(world, plan_entity);
+                let mut entity = world.entity_mut(source);
                 let mut props = entity.get_mut::<Props>().unwrap();
+                let mut applied_any = false;
                 for (effect_entity, effect_name, effect) in effects_scratch.drain(..) {
                     if effect.plan_only {
                         debug!(
@@ -127,10 +231,14 @@ pub(crate) fn execute_plan(
                             "applying effect"
                         );
                         effect.apply(&mut props);
+                        applied_any = true;
                     }
                 }
+                if applied_any {
+                    replan_other_layers(world, plan_entity);
+                }
 
-                false
+                None
             }
             Ok(OperatorStatus::Ongoing) => {
                 debug!(?plan_entity, ?plan_name, "operator ongoing");
@@ -139,7 +247,12 @@ pub(crate) fn execute_plan(
             }
             Ok(OperatorStatus::Failure) => {
                 debug!(?plan_entity, ?plan_name, "operator failed, aborting plan");
-                true
+                Some(failed_condition.map_or(
+                    PlanEndReason::OperatorFailed {
+                        operator: planned_operator.entity,
+                    },
+                    |condition| PlanEndReason::ConditionFailed { condition },
+                ))
             }
             Err(err) => {
                 debug!(
@@ -148,17 +261,41 @@ pub(crate) fn execute_plan(
                     ?err,
                     "operator system failed, aborting plan"
                 );
-                true
+                Some(PlanEndReason::OperatorFailed {
+                    operator: planned_operator.entity,
+                })
             }
         };
-        if force_replan
-            || world
+        let end_reason = end_reason.or_else(|| {
+            world
                 .entity(plan_entity)
                 .get::<Plan>()
                 .is_none_or(|plan| plan.is_empty())
+                .then_some(PlanEndReason::Completed)
+        });
+        if matches!(
+            end_reason,
+            Some(PlanEndReason::ConditionFailed { .. } | PlanEndReason::OperatorFailed { .. })
+        ) && world.entity(plan_entity).contains::<PlanRepair>()
+            && !world.entity(plan_entity).contains::<PlanOverride>()
+            && repair_plan(world, plan_entity, failed_condition)
         {
-            world.entity_mut(plan_entity).insert(Plan::default());
+            continue;
+        }
+        if let Some(end_reason) = end_reason {
+            end_plan(world, plan_entity, end_reason);
             debug!(?plan_entity, ?plan_name, "triggering replan");
         }
     }
+    record_execution_time(world, start.elapsed());
+}
+
+/// Ends the plan of `planner` for `end_reason` and clears it, so that the agent replans.
+pub(crate) fn end_plan(world: &mut World, planner: Entity, end_reason: PlanEndReason) {
+    record_plan_ended(world, planner, end_reason);
+    end_delegation(world, planner, end_reason == PlanEndReason::Completed);
+    if !end_plan_override(world, planner, end_reason) {
+        record_policy_plan_ended(world, planner, end_reason);
+    }
+    world.entity_mut(planner).insert(Plan::default());
 }
@@ -0,0 +1,177 @@
+//! Contains the [`PlanHistory`] component for inspecting the plans an agent ran in the past.
+
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use bevy_time::Time;
+
+use crate::{plan::mtr::Mtr, prelude::*};
+
+/// Opt-in ring buffer of the last [`Plan`]s of an agent. Insert this next to a [`Plan`] to start recording.
+/// Useful for figuring out why an agent behaves the way it does, e.g. through an inspector.
+#[derive(Component, Clone, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct PlanHistory {
+    /// How many plans are kept at most. When a new plan starts and the history is full, the oldest plan is dropped.
+    pub capacity: usize,
+    /// The recorded plans, oldest first. If the agent is currently running a plan, it is the last entry.
+    pub records: VecDeque<PlanRecord>,
+}
+
+impl Default for PlanHistory {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl PlanHistory {
+    /// Creates a new empty history that keeps the last `capacity` plans.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the record of the plan that is currently running, if any.
+    pub fn current(&self) -> Option<&PlanRecord> {
+        self.records.back().filter(|record| record.end.is_none())
+    }
+
+    fn current_mut(&mut self) -> Option<&mut PlanRecord> {
+        self.records
+            .back_mut()
+            .filter(|record| record.end.is_none())
+    }
+
+    fn push(&mut self, record: PlanRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+}
+
+/// A single plan recorded in a [`PlanHistory`].
+#[derive(Clone, Debug, PartialEq, Reflect)]
+pub struct PlanRecord {
+    /// The [`Mtr`] of the plan.
+    pub mtr: Mtr,
+    /// The [`Operator`]s that were planned, in order.
+    pub planned: Vec<Entity>,
+    /// The elapsed [`Time`] at which the plan was started.
+    pub started: Duration,
+    /// Information about how the plan ended. `None` while the plan is still running.
+    pub end: Option<PlanEnd>,
+    /// Every operator run of this plan, in order. [`OperatorStatus::Ongoing`] operators show up once per tick.
+    pub operator_runs: Vec<OperatorRun>,
+}
+
+/// When and why a [`PlanRecord`] ended.
+#[derive(Clone, Debug, PartialEq, Reflect)]
+pub struct PlanEnd {
+    /// The elapsed [`Time`] at which the plan ended.
+    pub time: Duration,
+    /// Why the plan ended.
+    pub reason: PlanEndReason,
+}
+
+/// The reason a [`PlanRecord`] ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum PlanEndReason {
+    /// All operators of the plan ran successfully.
+    Completed,
+    /// A [`Condition`] did not hold anymore when it was checked.
+    ConditionFailed {
+        /// The entity holding the [`Condition`] that failed.
+        condition: Entity,
+    },
+    /// An [`Operator`] returned [`OperatorStatus::Failure`], its system could not be run, it was despawned,
+    /// or it was unplugged from a [`Slot`].
+    OperatorFailed {
+        /// The entity holding the [`Operator`] that failed.
+        operator: Entity,
+    },
+    /// The plan was replaced, e.g. through [`UpdatePlan`] finding a plan with a higher priority.
+    Replaced,
+}
+
+/// A single run of an [`Operator`] recorded in a [`PlanRecord`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub struct OperatorRun {
+    /// The entity holding the [`Operator`].
+    pub operator: Entity,
+    /// The elapsed [`Time`] at which the operator ran.
+    pub time: Duration,
+    /// What the operator returned. Errors when running the operator system are recorded as [`OperatorStatus::Failure`].
+    pub status: OperatorStatus,
+}
+
+fn elapsed(world: &World) -> Duration {
+    world
+        .get_resource::<Time>()
+        .map(Time::elapsed)
+        .unwrap_or_default()
+}
+
+/// Records that `plan` was inserted on `entity`, closing the previously running plan if there was one.
+pub(crate) fn record_plan_replaced(world: &mut World, entity: Entity, plan: &Plan) {
+    let time = elapsed(world);
+    let Some(mut history) = world.get_mut::<PlanHistory>(entity) else {
+        return;
+    };
+    if let Some(current) = history.current_mut() {
+        current.end = Some(PlanEnd {
+            time,
+            reason: PlanEndReason::Replaced,
+        });
+    }
+    if plan.is_empty() {
+        return;
+    }
+    history.push(PlanRecord {
+        mtr: plan.mtr.clone(),
+        planned: plan
+            .operators_left
+            .iter()
+            .map(|&idx| plan.nodes[idx].entity)
+            .collect(),
+        started: time,
+        end: None,
+        operator_runs: Vec::new(),
+    });
+}
+
+/// Records that `operator` ran as part of the current plan of `entity`.
+pub(crate) fn record_operator_run(
+    world: &mut World,
+    entity: Entity,
+    operator: Entity,
+    status: OperatorStatus,
+) {
+    let time = elapsed(world);
+    let Some(mut history) = world.get_mut::<PlanHistory>(entity) else {
+        return;
+    };
+    if let Some(current) = history.current_mut() {
+        current.operator_runs.push(OperatorRun {
+            operator,
+            time,
+            status,
+        });
+    }
+}
+
+/// Records that the current plan of `entity` ended for the given reason.
+pub(crate) fn record_plan_ended(world: &mut World, entity: Entity, reason: PlanEndReason) {
+    let time = elapsed(world);
+    let Some(mut history) = world.get_mut::<PlanHistory>(entity) else {
+        return;
+    };
+    if let Some(current) = history.current_mut() {
+        current.end = Some(PlanEnd { time, reason });
+    }
+}
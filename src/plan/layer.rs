@@ -0,0 +1,96 @@
+//! Contains [`PlanLayers`] for running several independent [`Plan`]s on one agent.
+
+use alloc::slice;
+use bevy_ecs::relationship::{RelatedSpawner, RelatedSpawnerCommands};
+use core::iter::Copied;
+
+use crate::prelude::*;
+
+/// Points from a plan layer to the agent it plans for. Created with [`plan_layers!`].
+///
+/// A layer is an entity with its own domain, [`Plan`] and [`Mtr`](crate::plan::mtr::Mtr), e.g. one layer for
+/// locomotion and one for looking around. All layers of an agent read and write the [`Props`] of the agent instead of their own.
+/// When the operator of one layer applies [`Effect`]s, [`UpdatePlan`] is triggered on all other layers of the agent.
+/// Use [`Name`] to tell layers apart.
+#[derive(Component, Deref, Reflect, Debug, PartialEq, Eq, Clone)]
+#[relationship(relationship_target = PlanLayers)]
+#[reflect(Component)]
+#[require(Plan)]
+pub struct PlanLayerOf(pub Entity);
+
+/// Relationship target for plan layers. Created with [`plan_layers!`].
+#[derive(Component, Clone, Deref, Reflect, Debug, Default, PartialEq, Eq)]
+#[relationship_target(relationship = PlanLayerOf, linked_spawn)]
+#[reflect(Component)]
+#[require(Props)]
+pub struct PlanLayers(Vec<Entity>);
+
+impl<'a> IntoIterator for &'a PlanLayers {
+    type Item = Entity;
+    type IntoIter = Copied<slice::Iter<'a, Entity>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl PlanLayers {
+    /// Returns the layer with the given [`Name`].
+    pub fn named(&self, world: &World, name: &str) -> Option<Entity> {
+        self.iter()
+            .find(|&layer| world.get::<Name>(layer).is_some_and(|n| n.as_str() == name))
+    }
+}
+
+/// Shorthand for a [`RelatedSpawner`] for [`PlanLayerOf`] relations.
+pub type PlanLayerSpawner<'w> = RelatedSpawner<'w, PlanLayerOf>;
+
+/// Shorthand for a [`RelatedSpawnerCommands`] for [`PlanLayerOf`] relations.
+pub type PlanLayerSpawnerCommands<'w> = RelatedSpawnerCommands<'w, PlanLayerOf>;
+
+/// Shorthand for creating a [`PlanLayers`] relation
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn spawn_agent(mut commands: Commands) {
+///     commands.spawn((
+///         Name::new("Guard"),
+///         plan_layers![
+///             (
+///                 Name::new("legs"),
+///                 Operator::new(|_: In<OperatorInput>| OperatorStatus::Ongoing),
+///             ),
+///             (
+///                 Name::new("head"),
+///                 Operator::new(|_: In<OperatorInput>| OperatorStatus::Ongoing),
+///             ),
+///         ],
+///     ));
+/// }
+/// ```
+#[macro_export]
+macro_rules! plan_layers {
+    [$($layer:expr),*$(,)?] => {
+        ::bevy::prelude::related!($crate::prelude::PlanLayers[$($layer),*])
+    };
+}
+
+pub use plan_layers;
+
+/// Triggers [`UpdatePlan`] on all layers of the agent of `layer`, except `layer` itself.
+pub(crate) fn replan_other_layers(world: &mut World, layer: Entity) {
+    let Some(agent) = world.get::<PlanLayerOf>(layer).map(|layer_of| layer_of.0) else {
+        return;
+    };
+    let others: Vec<_> = world
+        .get::<PlanLayers>(agent)
+        .into_iter()
+        .flatten()
+        .filter(|&other| other != layer)
+        .collect();
+    for other in others {
+        debug!(?layer, ?other, "effects applied, replanning other layer");
+        world.trigger(UpdatePlan::new(other));
+    }
+}
@@ -5,8 +5,20 @@ use bevy_ecs::{entity_disabling::Disabled, query::QueryEntityError};
 
 use crate::{plan::mtr::Mtr, prelude::*};
 
+pub mod async_planning;
+pub mod cache;
 pub(crate) mod execution;
+pub mod history;
+pub mod layer;
 pub mod mtr;
+pub mod pause;
+pub mod policy;
+pub mod repair;
+pub mod reservation;
+pub mod scheduler;
+pub mod scripted;
+pub mod target;
+pub mod tick_rate;
 pub mod update;
 
 /// A full plan of operators to execute. If this is empty, either through manually clearing it, inserting it, when it runs out of operators, or fails to execute them,
@@ -19,9 +31,11 @@ pub struct Plan {
     #[reflect(ignore)]
     #[deref]
     pub operators_left: VecDeque<usize>,
-    /// Currently executing tasks.
+    /// The compound tasks enclosing the [`Operator`] at the front of [`Plan::operators_left`], as indices into [`Plan::nodes`],
+    /// outermost first. Used by [`PlanRepair`](repair::PlanRepair) to find what to decompose again.
     pub track: Vec<usize>,
-    /// All [`Operator`]s that were in [`Plan::operators_left`] when the plan was createdk
+    /// All [`Operator`]s that were in [`Plan::operators_left`] when the plan was created,
+    /// and the [`CompoundTask`]s they were decomposed from.
     pub nodes: Vec<TaskNode>,
     /// The [`Mtr`] of the full plan when it was created.
     pub mtr: Mtr,
@@ -38,6 +52,23 @@ impl Plan {
         *self = Self::new();
     }
 
+    /// A plan consisting of a single operator.
+    pub(crate) fn single(operator: Entity, conditions: Vec<Entity>) -> Self {
+        Self {
+            operators_left: [0].into(),
+            nodes: [TaskNode {
+                entity: operator,
+                effects: vec![],
+                conditions,
+                composite: false,
+                parent: None,
+            }]
+            .into(),
+            mtr: Mtr::default(),
+            track: vec![],
+        }
+    }
+
     pub(crate) fn add_node(&mut self, node: TaskNode) -> usize {
         self.nodes.push(node);
         self.nodes.len() - 1
@@ -45,20 +76,66 @@ impl Plan {
 
     pub(crate) fn merge(&mut self, mut other: Plan) {
         let len = self.nodes.len();
+        for node in &mut other.nodes {
+            node.parent = node.parent.map(|parent| parent + len);
+        }
         self.nodes.append(&mut other.nodes);
         for idx in other.operators_left {
             self.push_back(idx + len);
         }
     }
+
+    /// Records that all nodes of this plan were decomposed from the compound task `task`.
+    pub(crate) fn enclose(&mut self, task: Entity) {
+        let index = self.nodes.len();
+        for node in &mut self.nodes {
+            if node.parent.is_none() {
+                node.parent = Some(index);
+            }
+        }
+        self.nodes.push(TaskNode {
+            entity: task,
+            composite: true,
+            effects: vec![],
+            conditions: vec![],
+            parent: None,
+        });
+    }
+
+    /// Returns whether the node at `index` is the node at `ancestor` or was decomposed from it.
+    pub(crate) fn is_within(&self, index: usize, ancestor: usize) -> bool {
+        let mut current = Some(index);
+        while let Some(index) = current {
+            if index == ancestor {
+                return true;
+            }
+            current = self.nodes[index].parent;
+        }
+        false
+    }
+
+    /// Sets [`Plan::track`] to the compound tasks enclosing the operator at the front.
+    pub(crate) fn update_track(&mut self) {
+        self.track.clear();
+        let mut current = self.front().and_then(|&index| self.nodes[index].parent);
+        while let Some(index) = current {
+            self.track.push(index);
+            current = self.nodes[index].parent;
+        }
+        self.track.reverse();
+    }
 }
 
-/// An entry in [`Plan::operators_left`], representing an operator that is either currently executing or waiting to execute.
+/// An entry in [`Plan::nodes`], representing an operator that is either currently executing or waiting to execute,
+/// or a compound task that operators were decomposed from.
 #[derive(Clone, Debug, PartialEq, Eq, Reflect)]
 pub struct TaskNode {
-    /// The [`Entity`] of the [`Operator`].
+    /// The [`Entity`] of the [`Operator`] or [`CompoundTask`].
     pub entity: Entity,
-    /// Whenever or not this has an associated [`Operator`]
+    /// Whenever or not this is a [`CompoundTask`] instead of an [`Operator`]
     pub composite: bool,
+    /// The index in [`Plan::nodes`] of the compound task this was decomposed from, if any.
+    pub parent: Option<usize>,
     /// The last operator of a compound task will also inherit effects from higher-up compound tasks.
     pub effects: Vec<Entity>,
     /// The [`Condition`]s that need to be fulfilled for the operator to be run.
@@ -120,8 +197,9 @@ pub(crate) fn log_plan(
             log.push_str(&format!("      - {condition_name}\n"));
         }
     }
-    log.push_str(&format!("- total operators ({})\n", plan.nodes.len()));
-    for operator in &plan.nodes {
+    let operators: Vec<_> = plan.nodes.iter().filter(|node| !node.composite).collect();
+    log.push_str(&format!("- total operators ({})\n", operators.len()));
+    for operator in operators {
         let operator_name = name(operator.entity)?;
         log.push_str(&format!("  - {operator_name}\n"));
     }
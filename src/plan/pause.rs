@@ -0,0 +1,96 @@
+//! Contains [`PlanPaused`] and [`GlobalPlanPause`] for freezing agents, and [`StepPlan`] for stepping through them.
+
+use crate::{plan::tick_rate::PlanTickRate, prelude::*};
+
+/// Insert this on an agent to freeze it. Its [`Plan`] is neither executed nor replanned when empty,
+/// until this component is removed again. Trigger [`StepPlan`] to run a paused agent for a single tick.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct PlanPaused;
+
+/// [`EntityEvent`] for running a paused agent for exactly one tick of the [`BaePlugin`] schedule.
+/// Triggering it multiple times runs the agent for that many ticks.
+/// Steps wait for the agent's [`PlanTickRate`], if it has one.
+#[derive(EntityEvent)]
+pub struct StepPlan {
+    /// The paused agent to run.
+    #[event_target]
+    pub entity: Entity,
+}
+
+impl From<Entity> for StepPlan {
+    fn from(entity: Entity) -> Self {
+        Self { entity }
+    }
+}
+
+impl StepPlan {
+    /// Create a new [`StepPlan`] event for the given entity. Usually called with the [`EntityCommands::trigger`] API.
+    pub fn new(entity: Entity) -> Self {
+        Self::from(entity)
+    }
+}
+
+/// While this resource exists, all agents are paused as if they had [`PlanPaused`].
+/// Call [`GlobalPlanPause::step`] to run all agents for a single tick.
+#[derive(Resource, Debug, Default)]
+pub struct GlobalPlanPause {
+    pending_steps: u32,
+    stepping: bool,
+}
+
+impl GlobalPlanPause {
+    /// Runs all agents for one more tick of the [`BaePlugin`] schedule.
+    pub fn step(&mut self) {
+        self.pending_steps += 1;
+    }
+
+    /// Returns how many ticks all agents will still run for.
+    pub fn pending_steps(&self) -> u32 {
+        self.pending_steps
+    }
+}
+
+/// Steps requested through [`StepPlan`] that were not run yet.
+#[derive(Component, Debug, Default)]
+pub(crate) struct PlanSteps {
+    pending: u32,
+    stepping: bool,
+}
+
+/// Returns whether an agent runs in the current tick, given whether it has [`PlanPaused`].
+pub(crate) fn is_running(
+    paused: bool,
+    steps: Option<&PlanSteps>,
+    global: Option<&GlobalPlanPause>,
+) -> bool {
+    if global.is_some_and(|global| global.stepping) || steps.is_some_and(|steps| steps.stepping) {
+        return true;
+    }
+    !paused && global.is_none()
+}
+
+pub(crate) fn step_plan(step: On<StepPlan>, mut commands: Commands) {
+    commands
+        .entity(step.entity)
+        .entry::<PlanSteps>()
+        .or_default()
+        .and_modify(|mut steps| steps.pending += 1);
+}
+
+/// Consumes the steps that run in the current tick.
+pub(crate) fn advance_plan_steps(
+    global: Option<ResMut<GlobalPlanPause>>,
+    mut steps: Query<(&mut PlanSteps, Option<&PlanTickRate>)>,
+) {
+    if let Some(mut global) = global {
+        global.stepping = global.pending_steps > 0;
+        global.pending_steps = global.pending_steps.saturating_sub(1);
+    }
+    for (mut steps, tick_rate) in &mut steps {
+        steps.stepping = steps.pending > 0 && tick_rate.is_none_or(PlanTickRate::is_due);
+        if steps.stepping {
+            steps.pending -= 1;
+        }
+    }
+}
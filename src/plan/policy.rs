@@ -0,0 +1,159 @@
+//! Contains [`ReplanPolicy`] for controlling how eagerly an agent replans.
+
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use bevy_time::Time;
+
+use crate::{
+    plan::{history::PlanEndReason, mtr::Mtr},
+    prelude::*,
+};
+
+/// Insert this next to a [`Plan`] to limit how eagerly the agent replans.
+#[derive(Component, Clone, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+#[require(Plan, ReplanPolicyState)]
+pub struct ReplanPolicy {
+    /// How long a new plan runs at least before an [`UpdatePlan`] may replace it.
+    /// Plans that end by themselves are not affected.
+    pub min_commitment: Duration,
+    /// How long to wait before replanning after a plan failed.
+    pub failure_cooldown: Duration,
+    /// Every time the same plan fails again in a row, the cooldown is multiplied by this.
+    pub backoff_factor: f32,
+    /// The cooldown never gets longer than this.
+    pub max_cooldown: Duration,
+    /// Whether to log a warning when the agent keeps switching back and forth between the same two plans.
+    pub warn_on_oscillation: bool,
+}
+
+impl Default for ReplanPolicy {
+    fn default() -> Self {
+        Self {
+            min_commitment: Duration::ZERO,
+            failure_cooldown: Duration::ZERO,
+            backoff_factor: 2.0,
+            max_cooldown: Duration::from_secs(5),
+            warn_on_oscillation: true,
+        }
+    }
+}
+
+/// Bookkeeping for [`ReplanPolicy`].
+#[derive(Component, Clone, Debug, Default)]
+pub(crate) struct ReplanPolicyState {
+    plan_started: Duration,
+    cooldown_until: Duration,
+    consecutive_failures: u32,
+    last_failed_mtr: Option<Mtr>,
+    recent_mtrs: VecDeque<Mtr>,
+}
+
+impl ReplanPolicy {
+    fn cooldown(&self, consecutive_failures: u32) -> Duration {
+        let factor = self
+            .backoff_factor
+            .max(1.0)
+            .powi(consecutive_failures.saturating_sub(1) as i32);
+        Duration::try_from_secs_f32(self.failure_cooldown.as_secs_f32() * factor)
+            .unwrap_or(self.max_cooldown)
+            .min(self.max_cooldown)
+    }
+}
+
+fn elapsed(world: &World) -> Duration {
+    world
+        .get_resource::<Time>()
+        .map(Time::elapsed)
+        .unwrap_or_default()
+}
+
+/// Returns whether an agent has to keep its current plan because of [`ReplanPolicy::min_commitment`].
+pub(crate) fn is_committed(
+    policy: &ReplanPolicy,
+    state: &ReplanPolicyState,
+    plan: &Plan,
+    now: Duration,
+) -> bool {
+    !plan.is_empty() && now.saturating_sub(state.plan_started) < policy.min_commitment
+}
+
+/// Returns whether an agent has to wait before replanning because of [`ReplanPolicy::failure_cooldown`].
+pub(crate) fn is_cooling_down(state: Option<&ReplanPolicyState>, now: Duration) -> bool {
+    state.is_some_and(|state| now < state.cooldown_until)
+}
+
+/// Records that the plan of `entity` ended, starting a cooldown if it failed.
+pub(crate) fn record_policy_plan_ended(world: &mut World, entity: Entity, reason: PlanEndReason) {
+    let now = elapsed(world);
+    let Ok(mut agent) = world.get_entity_mut(entity) else {
+        return;
+    };
+    let Some(policy) = agent.get::<ReplanPolicy>().cloned() else {
+        return;
+    };
+    let mtr = agent.get::<Plan>().map(|plan| plan.mtr.clone());
+    let Some(mut state) = agent.get_mut::<ReplanPolicyState>() else {
+        return;
+    };
+    match reason {
+        PlanEndReason::ConditionFailed { .. } | PlanEndReason::OperatorFailed { .. } => {
+            let same_plan = state
+                .last_failed_mtr
+                .as_ref()
+                .zip(mtr.as_ref())
+                .is_some_and(|(a, b)| a.0 == b.0);
+            state.consecutive_failures = if same_plan {
+                state.consecutive_failures + 1
+            } else {
+                1
+            };
+            state.cooldown_until = now + policy.cooldown(state.consecutive_failures);
+            state.last_failed_mtr = mtr;
+        }
+        PlanEndReason::Completed => {
+            state.consecutive_failures = 0;
+            state.last_failed_mtr = None;
+        }
+        PlanEndReason::Replaced => {}
+    }
+}
+
+/// Tracks when plans start and warns about oscillating plans.
+pub(crate) fn track_policy_plans(
+    insert: On<Insert, Plan>,
+    time: Option<Res<Time>>,
+    mut agents: Query<(NameOrEntity, &Plan, &ReplanPolicy, &mut ReplanPolicyState)>,
+) {
+    let Ok((name, plan, policy, mut state)) = agents.get_mut(insert.entity) else {
+        return;
+    };
+    if plan.is_empty() {
+        return;
+    }
+    state.plan_started = time.map(|time| time.elapsed()).unwrap_or_default();
+    if state
+        .recent_mtrs
+        .back()
+        .is_some_and(|mtr| mtr.0 == plan.mtr.0)
+    {
+        return;
+    }
+    state.recent_mtrs.push_back(plan.mtr.clone());
+    while state.recent_mtrs.len() > 4 {
+        state.recent_mtrs.pop_front();
+    }
+    let oscillating = state.recent_mtrs.len() == 4
+        && state.recent_mtrs[0].0 == state.recent_mtrs[2].0
+        && state.recent_mtrs[1].0 == state.recent_mtrs[3].0;
+    if policy.warn_on_oscillation && oscillating {
+        warn!(
+            entity=?name.entity,
+            name=?name.name,
+            first=%state.recent_mtrs[0],
+            second=%state.recent_mtrs[1],
+            "agent keeps switching between the same two plans"
+        );
+    }
+}
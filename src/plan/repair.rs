@@ -0,0 +1,140 @@
+//! Contains [`PlanRepair`] for repairing plans instead of replanning from scratch.
+
+use bevy_mod_props::PropsExt;
+
+use crate::{
+    plan::{mtr::Mtr, target::props_source, update::replace_plan},
+    prelude::*,
+    task::compound::{
+        DecomposeInput, DecomposeResult, TaskSource, TypeErasedCompoundTask, WorldState,
+    },
+};
+
+/// Insert this next to a [`Plan`] to repair it when an [`Operator`] fails or a [`Condition`] stops holding,
+/// instead of throwing the whole plan away.
+///
+/// On failure, the compound task enclosing the failed step is decomposed again with the current [`Props`],
+/// and the result replaces the rest of that compound task in the plan. The steps after it are kept.
+/// If the compound task can't be decomposed anymore, or its own [`Condition`]s don't hold, the next enclosing compound task
+/// in [`Plan::track`] is tried. Only if none of them can be repaired, the agent replans from scratch.
+///
+/// If a [`Condition`] inherited from a compound task failed, repairing starts above that compound task.
+/// Repaired plans are not checked against the [`Mtr`] of the running plan.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+#[require(Plan)]
+pub struct PlanRepair;
+
+/// Tries to repair the plan of `agent` after the step at the front of it failed.
+/// Returns whether the plan was repaired.
+pub(crate) fn repair_plan(
+    world: &mut World,
+    agent: Entity,
+    failed_condition: Option<Entity>,
+) -> bool {
+    let Some(plan) = world.get::<Plan>(agent).cloned() else {
+        return false;
+    };
+    // The compound tasks enclosing the failed step, innermost first.
+    // The outermost one is the whole domain, so redoing it is the same as replanning.
+    let mut track: Vec<_> = plan.track.iter().skip(1).rev().copied().collect();
+    // A condition inherited from a compound task means that the whole compound task has to be redone
+    if let Some(task) = failed_condition
+        .and_then(|condition| world.get::<ConditionOf>(condition))
+        .map(|condition_of| condition_of.0)
+        && let Some(position) = track.iter().position(|&idx| plan.nodes[idx].entity == task)
+    {
+        track.drain(..=position);
+    }
+    for idx in track {
+        if try_repair_subtree(world, agent, &plan, idx) {
+            debug!(?agent, compound_task=?plan.nodes[idx].entity, "repaired plan");
+            return true;
+        }
+    }
+    false
+}
+
+/// Decomposes the compound task at `idx` in `plan` again and splices the result into the plan.
+fn try_repair_subtree(world: &mut World, agent: Entity, plan: &Plan, idx: usize) -> bool {
+    let task = plan.nodes[idx].entity;
+    let Some(compound_task) = world.get::<TypeErasedCompoundTask>(task).cloned() else {
+        return false;
+    };
+    let source = props_source(world, agent);
+    let world_state = WorldState::new(world.entity(source).props().clone());
+    let mut conditions = Vec::new();
+    for condition_entity in world
+        .get::<Conditions>(task)
+        .map(|conditions| conditions.to_vec())
+        .unwrap_or_default()
+    {
+        let Some(condition) = world.get::<Condition>(condition_entity) else {
+            continue;
+        };
+        if !world.condition_holds(condition, &world_state, agent) {
+            return false;
+        }
+        conditions.push(condition_entity);
+    }
+    let result = compound_task.decompose(
+        world,
+        DecomposeInput {
+            planner: agent,
+            compound_task: task,
+            world_state,
+            previous_mtr: Mtr::none(),
+            conditions,
+        },
+    );
+    world.flush();
+    let Ok(DecomposeResult::Success { sub_plan, .. }) = result else {
+        return false;
+    };
+    if sub_plan.is_empty() {
+        return false;
+    }
+
+    // The steps of the subtree are at the front, as decomposition is depth-first
+    let subtree_len = plan
+        .iter()
+        .take_while(|&&step| plan.is_within(step, idx))
+        .count();
+    // The effects of the compound task itself are applied by its parent, so they are kept as well
+    let subtree_tasks: Vec<_> = (0..plan.nodes.len())
+        .filter(|&node| node != idx && plan.is_within(node, idx))
+        .map(|node| plan.nodes[node].entity)
+        .collect();
+    // Effects of the subtree itself and everything above it were attached to its last step
+    let kept_effects: Vec<_> = plan
+        .iter()
+        .take(subtree_len)
+        .last()
+        .map(|&step| plan.nodes[step].effects.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|&effect| {
+            world
+                .get::<EffectOf>(effect)
+                .is_none_or(|effect_of| !subtree_tasks.contains(&effect_of.0))
+        })
+        .collect();
+
+    let mut repaired = Plan {
+        nodes: plan.nodes.clone(),
+        mtr: plan.mtr.clone(),
+        ..Plan::default()
+    };
+    let offset = repaired.nodes.len();
+    repaired.merge(sub_plan);
+    for node in &mut repaired.nodes[offset..] {
+        if node.parent.is_none() {
+            node.parent = plan.nodes[idx].parent;
+        }
+    }
+    if let Some(&last) = repaired.back() {
+        repaired.nodes[last].effects.extend(kept_effects);
+    }
+    repaired.extend(plan.iter().skip(subtree_len).copied());
+    replace_plan(world, agent, repaired)
+}
@@ -0,0 +1,180 @@
+//! Contains [`Reservations`] for keeping agents from planning to use the same thing.
+
+use core::fmt::{self, Display};
+
+use bevy_platform::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Something an agent can reserve, e.g. a bridge or a door.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+#[reflect(opaque, Debug, PartialEq, Hash)]
+pub enum ReservationKey {
+    /// A named resource.
+    Name(Ustr),
+    /// An entity.
+    Entity(Entity),
+}
+
+impl From<Ustr> for ReservationKey {
+    fn from(name: Ustr) -> Self {
+        Self::Name(name)
+    }
+}
+
+impl From<&str> for ReservationKey {
+    fn from(name: &str) -> Self {
+        Self::Name(name.into())
+    }
+}
+
+impl From<Entity> for ReservationKey {
+    fn from(entity: Entity) -> Self {
+        Self::Entity(entity)
+    }
+}
+
+impl Display for ReservationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Entity(entity) => write!(f, "{entity}"),
+        }
+    }
+}
+
+/// Insert this on a task to claim the given [`ReservationKey`]s for the agent whose plan contains the task.
+/// The claims are held while the plan runs and released when it ends, fails or is replaced.
+/// If another agent already holds one of them when the plan is about to start, the plan is rejected
+/// and the agent keeps its current plan, or replans if it has none.
+///
+/// [`Select`] skips subtasks with a claim that is already held by another agent.
+/// Use [`Condition::not_reserved`] to check claims anywhere else.
+/// Domains containing this are never cached by the [`PlanCache`](crate::plan::cache::PlanCache).
+#[derive(Component, Clone, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct Reserve(pub Vec<ReservationKey>);
+
+impl Reserve {
+    /// Claims the given keys.
+    pub fn new(keys: impl IntoIterator<Item = impl Into<ReservationKey>>) -> Self {
+        Self(keys.into_iter().map(Into::into).collect())
+    }
+}
+
+/// All claims made through [`Reserve`], keyed by the entity holding the [`Plan`] that claimed them.
+#[derive(Resource, Debug, Default)]
+pub struct Reservations {
+    owners: HashMap<ReservationKey, Entity>,
+    claims: HashMap<Entity, Vec<ReservationKey>>,
+}
+
+impl Reservations {
+    /// Returns the agent currently holding `key`.
+    pub fn owner(&self, key: impl Into<ReservationKey>) -> Option<Entity> {
+        self.owners.get(&key.into()).copied()
+    }
+
+    /// Returns whether `key` is held by an agent other than `agent`.
+    pub fn is_reserved_by_other(&self, key: impl Into<ReservationKey>, agent: Entity) -> bool {
+        self.owner(key).is_some_and(|owner| owner != agent)
+    }
+
+    /// Returns the keys held by `agent`.
+    pub fn claims(&self, agent: Entity) -> &[ReservationKey] {
+        self.claims.get(&agent).map_or(&[], Vec::as_slice)
+    }
+
+    fn claim(&mut self, agent: Entity, key: ReservationKey) {
+        if self.owners.insert(key, agent).is_none() {
+            self.claims.entry(agent).or_default().push(key);
+        }
+    }
+
+    fn release(&mut self, agent: Entity) {
+        for key in self.claims.remove(&agent).unwrap_or_default() {
+            self.owners.remove(&key);
+        }
+    }
+}
+
+/// Returns whether `key` is held by an agent other than `planner`.
+pub(crate) fn is_key_reserved_by_other(
+    world: &World,
+    key: ReservationKey,
+    planner: Entity,
+) -> bool {
+    world
+        .get_resource::<Reservations>()
+        .is_some_and(|reservations| reservations.is_reserved_by_other(key, planner))
+}
+
+/// Returns whether `task` claims something that is held by an agent other than `planner`.
+pub(crate) fn is_reserved_by_other(world: &World, task: Entity, planner: Entity) -> bool {
+    let (Some(reserve), Some(reservations)) = (
+        world.get::<Reserve>(task),
+        world.get_resource::<Reservations>(),
+    ) else {
+        return false;
+    };
+    reserve
+        .0
+        .iter()
+        .any(|&key| reservations.is_reserved_by_other(key, planner))
+}
+
+/// Returns the keys reserved by the tasks the rest of `plan` was decomposed from.
+pub(crate) fn plan_reservations(world: &World, plan: &Plan) -> Vec<ReservationKey> {
+    let mut visited = vec![false; plan.nodes.len()];
+    let mut keys = Vec::new();
+    for &step in plan.iter() {
+        let mut current = Some(step);
+        while let Some(idx) = current
+            && !visited[idx]
+        {
+            visited[idx] = true;
+            for &key in world
+                .get::<Reserve>(plan.nodes[idx].entity)
+                .into_iter()
+                .flat_map(|reserve| &reserve.0)
+            {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+            current = plan.nodes[idx].parent;
+        }
+    }
+    keys
+}
+
+/// Returns the first of `keys` that is held by an agent other than `planner`.
+pub(crate) fn reserved_by_other(
+    world: &World,
+    planner: Entity,
+    keys: &[ReservationKey],
+) -> Option<ReservationKey> {
+    let reservations = world.get_resource::<Reservations>()?;
+    keys.iter()
+        .copied()
+        .find(|&key| reservations.is_reserved_by_other(key, planner))
+}
+
+/// Claims `keys` for `planner`. Check them with [`reserved_by_other`] first.
+pub(crate) fn claim_reservations(world: &mut World, planner: Entity, keys: Vec<ReservationKey>) {
+    if let Some(mut reservations) = world.get_resource_mut::<Reservations>() {
+        for key in keys {
+            reservations.claim(planner, key);
+        }
+    }
+}
+
+/// Releases the reservations of a plan that ended or is being replaced.
+pub(crate) fn release_reservations(
+    replace: On<Replace, Plan>,
+    reservations: Option<ResMut<Reservations>>,
+) {
+    if let Some(mut reservations) = reservations {
+        reservations.release(replace.entity);
+    }
+}
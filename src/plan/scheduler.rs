@@ -0,0 +1,161 @@
+//! Contains the [`ReplanScheduler`] for spreading replans over multiple ticks.
+
+use core::time::Duration;
+
+use bevy_ecs::error::DefaultErrorHandler;
+use bevy_platform::{collections::HashMap, time::Instant};
+
+use crate::{
+    plan::{async_planning::AsyncPlanning, update::queue_replan},
+    prelude::*,
+};
+
+/// Opt-in queue for [`UpdatePlan`]s. While this resource exists, [`UpdatePlan`]s are not processed immediately.
+/// Instead, they are queued and processed in [`BaeSystems::ExecutePlan`], highest [`ReplanPriority`] first,
+/// until the [`ReplanBudget`] of the tick is used up. The remaining requests are carried over to the next tick.
+///
+/// Requests for an agent that is already queued are merged into the existing request.
+#[derive(Resource, Clone, Debug)]
+pub struct ReplanScheduler {
+    /// How many replans are processed per tick.
+    pub budget: ReplanBudget,
+    /// After waiting this many ticks, a request is processed before all requests that waited less, regardless of priority.
+    /// This prevents agents with a low priority from never getting to replan. `None` disables this protection.
+    /// Defaults to 60 ticks.
+    pub max_wait: Option<u32>,
+    queue: HashMap<Entity, QueuedReplan>,
+    tick: u64,
+    next_order: u64,
+}
+
+impl Default for ReplanScheduler {
+    fn default() -> Self {
+        Self {
+            budget: ReplanBudget::default(),
+            max_wait: Some(60),
+            queue: HashMap::default(),
+            tick: 0,
+            next_order: 0,
+        }
+    }
+}
+
+/// How many replans the [`ReplanScheduler`] processes per tick. At least one replan is always processed if any are queued.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplanBudget {
+    /// Process all queued replans.
+    #[default]
+    Unlimited,
+    /// Process at most this many replans.
+    Count(usize),
+    /// Stop processing once this much time was spent replanning.
+    Time(Duration),
+}
+
+/// The priority of an agent in the [`ReplanScheduler`]. Higher priorities replan first. Agents without this have priority `0`.
+#[derive(
+    Component, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Reflect, Deref, DerefMut,
+)]
+#[reflect(Component)]
+pub struct ReplanPriority(pub u32);
+
+#[derive(Clone, Copy, Debug)]
+struct QueuedReplan {
+    priority: ReplanPriority,
+    queued_at: u64,
+    order: u64,
+}
+
+impl ReplanScheduler {
+    /// Creates a new scheduler with the given budget and starvation protection after 60 ticks.
+    pub fn new(budget: ReplanBudget) -> Self {
+        Self {
+            budget,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of agents waiting to replan.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns whether no agent is waiting to replan.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns whether `entity` is waiting to replan.
+    pub fn is_queued(&self, entity: Entity) -> bool {
+        self.queue.contains_key(&entity)
+    }
+
+    /// Queues a replan for `entity`. If it is already queued, only its priority is raised if needed.
+    pub fn enqueue(&mut self, entity: Entity, priority: ReplanPriority) {
+        let (tick, order) = (self.tick, self.next_order);
+        let queued = self.queue.entry(entity).or_insert_with(|| QueuedReplan {
+            priority,
+            queued_at: tick,
+            order,
+        });
+        queued.priority = queued.priority.max(priority);
+        self.next_order += 1;
+    }
+
+    /// Removes a queued replan for `entity`, returning whether there was one.
+    pub fn cancel(&mut self, entity: Entity) -> bool {
+        self.queue.remove(&entity).is_some()
+    }
+
+    /// Returns all queued agents in the order they will be processed.
+    fn sorted(&self) -> Vec<Entity> {
+        let starving = |queued: &QueuedReplan| {
+            self.max_wait
+                .is_some_and(|max_wait| self.tick - queued.queued_at >= u64::from(max_wait))
+        };
+        let mut queued: Vec<_> = self.queue.iter().map(|(&entity, &q)| (entity, q)).collect();
+        queued.sort_by(|(_, a), (_, b)| {
+            starving(b)
+                .cmp(&starving(a))
+                .then_with(|| b.priority.cmp(&a.priority))
+                .then_with(|| a.order.cmp(&b.order))
+        });
+        queued.into_iter().map(|(entity, _)| entity).collect()
+    }
+}
+
+/// Processes the queued replans of the [`ReplanScheduler`] within its budget.
+pub(crate) fn process_replan_queue(world: &mut World) {
+    let Some(scheduler) = world.get_resource::<ReplanScheduler>() else {
+        return;
+    };
+    let budget = scheduler.budget;
+    let queued = scheduler.sorted();
+    let error_handler = world
+        .get_resource::<DefaultErrorHandler>()
+        .copied()
+        .unwrap_or_default();
+    let start = Instant::now();
+    for (processed, entity) in queued.into_iter().enumerate() {
+        let exhausted = processed > 0
+            && match budget {
+                ReplanBudget::Unlimited => false,
+                ReplanBudget::Count(count) => processed >= count,
+                ReplanBudget::Time(time) => start.elapsed() >= time,
+            };
+        if exhausted {
+            break;
+        }
+        world.resource_mut::<ReplanScheduler>().cancel(entity);
+        let Ok(agent) = world.get_entity(entity) else {
+            continue;
+        };
+        if !agent.contains::<Plan>() {
+            continue;
+        }
+        let is_async = agent.contains::<AsyncPlanning>();
+        queue_replan(&mut world.commands(), entity, is_async, error_handler);
+        world.flush();
+    }
+    world.resource_mut::<ReplanScheduler>().tick += 1;
+}
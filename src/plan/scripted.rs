@@ -0,0 +1,153 @@
+//! Contains [`PlanOverride`] for forcing an agent through a fixed list of operators.
+
+use crate::{
+    plan::{TaskNode, async_planning::PendingPlan, history::PlanEndReason, update::replace_plan},
+    prelude::*,
+};
+
+/// Insert this on an agent to run a fixed list of [`Operator`]s instead of what the domain decomposes into,
+/// e.g. for cutscenes or tutorials. The operators don't need to be part of the domain.
+///
+/// The [`Condition`]s and [`Effect`]s of the operators apply as usual. While the override is active,
+/// [`UpdatePlan`] is ignored. When the override plan completes or fails, this component is removed and the agent
+/// goes back to planning normally. Remove it yourself to cancel the override.
+///
+/// [`PlanOverrideStarted`] and [`PlanOverrideEnded`] are triggered on the agent at each transition.
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn start_cutscene(mut commands: Commands, npc: Single<Entity, With<Plan>>) {
+///     let walk = commands
+///         .spawn(Operator::new(|_: In<OperatorInput>| OperatorStatus::Success))
+///         .id();
+///     let wave = commands
+///         .spawn(Operator::new(|_: In<OperatorInput>| OperatorStatus::Success))
+///         .id();
+///     commands.entity(*npc).insert(PlanOverride::new([walk, wave]));
+/// }
+/// ```
+#[derive(Component, Clone, Debug, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+#[require(Plan)]
+pub struct PlanOverride {
+    operators: Vec<Entity>,
+    finished: bool,
+}
+
+impl PlanOverride {
+    /// Creates an override running the given [`Operator`]s in order.
+    pub fn new(operators: impl IntoIterator<Item = Entity>) -> Self {
+        Self {
+            operators: operators.into_iter().collect(),
+            finished: false,
+        }
+    }
+
+    /// The [`Operator`]s this override runs.
+    pub fn operators(&self) -> &[Entity] {
+        &self.operators
+    }
+}
+
+/// [`EntityEvent`] triggered when a [`PlanOverride`] takes over an agent.
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct PlanOverrideStarted {
+    /// The agent running the override.
+    #[event_target]
+    pub entity: Entity,
+}
+
+/// [`EntityEvent`] triggered when a [`PlanOverride`] stops and the agent goes back to planning normally.
+#[derive(EntityEvent, Clone, Copy, Debug)]
+pub struct PlanOverrideEnded {
+    /// The agent that ran the override.
+    #[event_target]
+    pub entity: Entity,
+    /// Why the override stopped.
+    pub reason: PlanOverrideEndReason,
+}
+
+/// Why a [`PlanOverride`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum PlanOverrideEndReason {
+    /// All operators ran successfully.
+    Completed,
+    /// An operator failed or one of its conditions was not fulfilled.
+    Failed(PlanEndReason),
+    /// The [`PlanOverride`] was removed or replaced before it finished.
+    Cancelled,
+}
+
+pub(crate) fn start_plan_override(insert: On<Insert, PlanOverride>, mut commands: Commands) {
+    let entity = insert.entity;
+    commands.queue(move |world: &mut World| {
+        let Some(plan_override) = world.get::<PlanOverride>(entity) else {
+            return;
+        };
+        let mut plan = Plan::new();
+        for &operator in &plan_override.operators {
+            let node = TaskNode {
+                entity: operator,
+                composite: false,
+                effects: world
+                    .get::<Effects>(operator)
+                    .map(|effects| effects.to_vec())
+                    .unwrap_or_default(),
+                conditions: world
+                    .get::<Conditions>(operator)
+                    .map(|conditions| conditions.to_vec())
+                    .unwrap_or_default(),
+                parent: None,
+            };
+            let idx = plan.add_node(node);
+            plan.push_back(idx);
+        }
+        // Planning that is still in flight would overwrite the override
+        world.entity_mut(entity).remove::<PendingPlan>();
+        if !replace_plan(world, entity, plan) {
+            debug!(
+                ?entity,
+                "plan override needs a reservation held by another agent"
+            );
+            world.entity_mut(entity).remove::<PlanOverride>();
+            return;
+        }
+        debug!(?entity, "started plan override");
+        world.trigger(PlanOverrideStarted { entity });
+    });
+}
+
+pub(crate) fn cancel_plan_override(
+    replace: On<Replace, PlanOverride>,
+    overrides: Query<&PlanOverride>,
+    mut commands: Commands,
+) {
+    let entity = replace.entity;
+    if overrides.get(entity).is_ok_and(|o| o.finished) {
+        return;
+    }
+    debug!(?entity, "cancelled plan override");
+    // If the override is replaced by another one, that one inserts its plan afterwards
+    commands.entity(entity).try_insert(Plan::default());
+    commands.trigger(PlanOverrideEnded {
+        entity,
+        reason: PlanOverrideEndReason::Cancelled,
+    });
+}
+
+/// Ends the [`PlanOverride`] of `entity` if it has one. Returns whether it had one.
+pub(crate) fn end_plan_override(world: &mut World, entity: Entity, reason: PlanEndReason) -> bool {
+    let Some(mut plan_override) = world.get_mut::<PlanOverride>(entity) else {
+        return false;
+    };
+    plan_override.finished = true;
+    world.entity_mut(entity).remove::<PlanOverride>();
+    let reason = match reason {
+        PlanEndReason::Completed => PlanOverrideEndReason::Completed,
+        reason => PlanOverrideEndReason::Failed(reason),
+    };
+    debug!(?entity, ?reason, "plan override ended");
+    world.trigger(PlanOverrideEnded { entity, reason });
+    true
+}
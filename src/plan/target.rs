@@ -0,0 +1,53 @@
+//! Contains [`PlanTarget`] for planning on behalf of another entity.
+
+use crate::{plan::layer::PlanLayerOf, prelude::*};
+
+/// Insert this next to a [`Plan`] to plan for an entity other than the one holding the [`Plan`],
+/// e.g. a squad commander planning for one of its members, or a vehicle whose AI lives on a child entity.
+///
+/// The [`Operator`]s find the target in [`OperatorInput::actor`] and the props source in [`OperatorInput::props`].
+/// Without this component, the actor and props source are the agent of a plan layer (see [`PlanLayerOf`]),
+/// or the entity holding the [`Plan`] otherwise.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+#[require(Plan)]
+pub struct PlanTarget {
+    /// The entity the [`Operator`]s act on.
+    pub actor: Entity,
+    /// The entity whose [`Props`] are used for planning and execution.
+    /// If `None`, the [`Props`] of the entity holding the [`Plan`] are used.
+    pub props: Option<Entity>,
+}
+
+impl PlanTarget {
+    /// Plans for `actor`, using the [`Props`] of the entity holding the [`Plan`].
+    pub fn new(actor: Entity) -> Self {
+        Self { actor, props: None }
+    }
+
+    /// Uses the [`Props`] of `props` instead of those of the entity holding the [`Plan`].
+    pub fn with_props(mut self, props: Entity) -> Self {
+        self.props = Some(props);
+        self
+    }
+}
+
+/// Returns the entity the operators of `planner` act on.
+pub(crate) fn actor(world: &World, planner: Entity) -> Entity {
+    if let Some(target) = world.get::<PlanTarget>(planner) {
+        return target.actor;
+    }
+    world
+        .get::<PlanLayerOf>(planner)
+        .map_or(planner, |layer_of| layer_of.0)
+}
+
+/// Returns the entity whose [`Props`] `planner` works with.
+pub(crate) fn props_source(world: &World, planner: Entity) -> Entity {
+    if let Some(target) = world.get::<PlanTarget>(planner) {
+        return target.props.unwrap_or(planner);
+    }
+    world
+        .get::<PlanLayerOf>(planner)
+        .map_or(planner, |layer_of| layer_of.0)
+}
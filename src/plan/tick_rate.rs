@@ -0,0 +1,118 @@
+//! Contains [`PlanTickRate`] for running agents less often than every tick.
+
+use core::time::Duration;
+
+use bevy_time::Time;
+
+use crate::prelude::*;
+
+/// How often the [`Plan`] of an agent is executed and replanned. Agents without this run every tick of the [`BaePlugin`] schedule.
+///
+/// Useful for level-of-detail, e.g. letting far-away agents think less often. To drive the rate from game state,
+/// add a system to [`BaeSystems::UpdateTickRate`] that changes [`PlanTickRate::rate`]:
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn rate_by_distance(
+///     player: Single<&Transform, With<Camera>>,
+///     mut agents: Query<(&Transform, &mut PlanTickRate)>,
+/// ) {
+///     for (transform, mut tick_rate) in &mut agents {
+///         let distance = transform.translation.distance(player.translation);
+///         tick_rate.rate = if distance < 50.0 {
+///             TickRate::EveryTick
+///         } else {
+///             TickRate::Hz(2.0)
+///         };
+///     }
+/// }
+///
+/// App::new().add_systems(FixedUpdate, rate_by_distance.in_set(BaeSystems::UpdateTickRate));
+/// ```
+/// [`OperatorInput::delta`] holds the time that passed since the agent last ran.
+#[derive(Component, Clone, Debug, Default, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct PlanTickRate {
+    /// The rate at which the agent runs.
+    pub rate: TickRate,
+    ticks_since_run: u32,
+    last_run: Option<Duration>,
+    due: Option<Duration>,
+}
+
+/// How often an agent with a [`PlanTickRate`] runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub enum TickRate {
+    /// Run every tick of the [`BaePlugin`] schedule.
+    #[default]
+    EveryTick,
+    /// Run every n-th tick of the [`BaePlugin`] schedule. `0` is treated as `1`.
+    EveryNthTick(u32),
+    /// Run at most this many times per second, as measured by [`Time`]. A rate of `0` or below never runs.
+    Hz(f32),
+}
+
+impl PlanTickRate {
+    /// Creates a new tick rate.
+    pub fn new(rate: TickRate) -> Self {
+        Self {
+            rate,
+            ..Self::default()
+        }
+    }
+
+    /// Shorthand for [`TickRate::EveryNthTick`].
+    pub fn every_nth_tick(n: u32) -> Self {
+        Self::new(TickRate::EveryNthTick(n))
+    }
+
+    /// Shorthand for [`TickRate::Hz`].
+    pub fn hz(hz: f32) -> Self {
+        Self::new(TickRate::Hz(hz))
+    }
+
+    /// Whether the agent runs in the current tick.
+    pub fn is_due(&self) -> bool {
+        self.due.is_some()
+    }
+
+    /// The time that passed since the agent last ran, if it runs in the current tick.
+    pub(crate) fn due(&self) -> Option<Duration> {
+        self.due
+    }
+
+    fn tick(&mut self, now: Duration, delta: Duration) {
+        self.ticks_since_run += 1;
+        let is_due = match self.rate {
+            TickRate::EveryTick => true,
+            TickRate::EveryNthTick(n) => self.ticks_since_run >= n.max(1),
+            TickRate::Hz(hz) => {
+                hz > 0.0
+                    && self.last_run.is_none_or(|last_run| {
+                        now.saturating_sub(last_run).as_secs_f32() >= 1.0 / hz
+                    })
+            }
+        };
+        if !is_due {
+            self.due = None;
+            return;
+        }
+        self.due = Some(
+            self.last_run
+                .map_or(delta, |last_run| now.saturating_sub(last_run)),
+        );
+        self.last_run = Some(now);
+        self.ticks_since_run = 0;
+    }
+}
+
+/// Decides which agents run in the current tick.
+pub(crate) fn tick_plan_rates(time: Option<Res<Time>>, mut tick_rates: Query<&mut PlanTickRate>) {
+    let (now, delta) = time
+        .map(|time| (time.elapsed(), time.delta()))
+        .unwrap_or_default();
+    for mut tick_rate in &mut tick_rates {
+        tick_rate.tick(now, delta);
+    }
+}
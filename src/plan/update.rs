@@ -3,17 +3,34 @@
 use bevy_ecs::error::{DefaultErrorHandler, HandleError as _};
 use bevy_ecs::system::command::run_system_cached_with;
 use bevy_mod_props::PropsExt;
+use bevy_platform::time::Instant;
+use bevy_time::Time;
 use core::marker::PhantomData;
+use core::time::Duration;
 
-use crate::plan::TaskNode;
+use crate::diagnostics::{count_visited_task, record_replan, reset_visited_tasks};
+use crate::plan::async_planning::{AsyncPlanning, start_async_plan};
+use crate::plan::cache::{cache_plan, cached_plan, plan_cache_key};
+use crate::plan::history::{PlanEndReason, record_plan_ended, record_plan_replaced};
 use crate::plan::mtr::Mtr;
+use crate::plan::policy::{ReplanPolicyState, is_committed};
+use crate::plan::reservation::{claim_reservations, plan_reservations, reserved_by_other};
+use crate::plan::scheduler::{ReplanPriority, ReplanScheduler};
+use crate::plan::scripted::PlanOverride;
+use crate::plan::target::props_source;
 use crate::prelude::*;
-use crate::task::compound::{DecomposeInput, DecomposeResult, TypeErasedCompoundTask};
+use crate::task::compound::{
+    DecomposeInput, DecomposeResult, TaskSource, TypeErasedCompoundTask, WorldState,
+};
+use crate::task::delegate::end_delegation;
 
 /// [`EntityEvent`] for updating a plan. Trigger this on an entity with a [`Plan`] to update its plan.
 /// Updating it will only have an effect if the new plan found has a higher priority than the current one.
 /// This ensures that ongoing [`Sequence`]s are not suddenly interrupted when updating the plan.
 /// If you want to instead wipe the slate clean, insert [`Plan::new`] instead, or call [`Plan::clear`].
+/// If a [`ReplanScheduler`] exists, the update is queued and processed later instead of immediately.
+/// If the agent has a [`ReplanPolicy`], the update is ignored while the agent is committed to its current plan.
+/// While the agent runs a [`PlanOverride`], the update is ignored as well.
 #[derive(EntityEvent)]
 pub struct UpdatePlan {
     /// The entity holding the [`Plan`] to update.
@@ -51,20 +68,63 @@ pub(crate) fn update_plan(
     update: On<UpdatePlan>,
     mut commands: Commands,
     error_handler: Option<Res<DefaultErrorHandler>>,
+    async_planning: Query<(), With<AsyncPlanning>>,
+    scheduler: Option<ResMut<ReplanScheduler>>,
+    priorities: Query<&ReplanPriority>,
+    policies: Query<(&ReplanPolicy, &ReplanPolicyState, &Plan)>,
+    time: Option<Res<Time>>,
+    overrides: Query<(), With<PlanOverride>>,
 ) {
     let entity = update.entity;
+    if overrides.contains(entity) {
+        debug!(?entity, "running a plan override, ignoring replan");
+        return;
+    }
+    if let Ok((policy, state, plan)) = policies.get(entity) {
+        let now = time.map(|time| time.elapsed()).unwrap_or_default();
+        if is_committed(policy, state, plan, now) {
+            debug!(?entity, "committed to the current plan, ignoring replan");
+            return;
+        }
+    }
+    if let Some(mut scheduler) = scheduler {
+        let priority = priorities.get(entity).copied().unwrap_or_default();
+        scheduler.enqueue(entity, priority);
+        return;
+    }
     let error_handler = error_handler.map(|h| *h).unwrap_or_default();
-    commands.queue(
-        run_system_cached_with(update_plan_inner, UpdatePlan { entity })
-            .handle_error_with(error_handler.0),
+    queue_replan(
+        &mut commands,
+        entity,
+        async_planning.contains(entity),
+        error_handler,
     );
 }
 
-fn update_plan_inner(
+/// Queues the actual planning for `entity`, either synchronously or through [`AsyncPlanning`].
+pub(crate) fn queue_replan(
+    commands: &mut Commands,
+    entity: Entity,
+    is_async: bool,
+    error_handler: DefaultErrorHandler,
+) {
+    if is_async {
+        commands.queue(
+            run_system_cached_with(start_async_plan, UpdatePlan { entity })
+                .handle_error_with(error_handler.0),
+        );
+    } else {
+        commands.queue(
+            run_system_cached_with(update_plan_inner, UpdatePlan { entity })
+                .handle_error_with(error_handler.0),
+        );
+    }
+}
+
+pub(crate) fn update_plan_inner(
     update: In<UpdatePlan>,
     world: &mut World,
     mut conditions: Local<QueryState<(Entity, &Condition)>>,
-    mut effects: Local<QueryState<Entity, With<Effect>>>,
     mut tasks: Local<
         QueryState<
             (Entity, Has<Operator>, Option<&TypeErasedCompoundTask>),
@@ -73,13 +133,25 @@ fn update_plan_inner(
     >,
 ) -> Result {
     let root = update.entity;
+    if world.entity(root).contains::<PlanOverride>() {
+        return Ok(());
+    }
 
-    let mut world_state = world.entity(update.entity).props().clone();
+    #[cfg(feature = "record")]
+    crate::record::record_replan(world, root);
+    let source = props_source(world, root);
+    let world_state = WorldState::new(world.entity(source).props().clone());
     let mut initial_conditions = Vec::new();
     if let Some(condition_relations) = world.get::<Conditions>(root) {
         for (entity, condition) in conditions.iter_many(world, condition_relations) {
-            let is_fulfilled = condition.is_fullfilled(&mut world_state);
+            let is_fulfilled = world.condition_holds(condition, &world_state, root);
             if !is_fulfilled {
+                record_plan_ended(
+                    world,
+                    root,
+                    PlanEndReason::ConditionFailed { condition: entity },
+                );
+                end_delegation(world, root, false);
                 world.entity_mut(root).insert(Plan::default());
                 return Ok(());
             }
@@ -97,26 +169,31 @@ fn update_plan_inner(
         world.entity_mut(root).insert(Plan::default());
         return Err(BevyError::from("Called `update_plan` for an entity without any tasks. Ensure it has either an `Operator` or a `CompoundTask` like `Select` or `Sequence`".to_string()));
     };
-    let mut plan = if has_operator {
+    reset_visited_tasks(world);
+    if has_operator {
         // well that was easy: this root has just a single operator
-        Plan {
-            operators_left: [0].into(),
-            nodes: [TaskNode {
-                entity,
-                effects: vec![],
-                conditions: initial_conditions,
-                composite: false,
-            }]
-            .into(),
-            mtr: Mtr::default(),
-            track: vec![],
-        }
+        count_visited_task(world);
+        record_replan(world, root, Duration::ZERO);
+        insert_plan(world, root, Plan::single(entity, initial_conditions));
     } else if let Some(compound_task) = compound_task {
-        let previous_mtr = if let Some(plan) = world.entity(root).get::<Plan>() {
-            plan.mtr.clone()
-        } else {
-            Mtr::none()
-        };
+        let previous_mtr = previous_mtr(world, root);
+        let cache_key = plan_cache_key(world, root, &world_state);
+        // Cached plans are only valid when there is no running plan that could reject them
+        if previous_mtr.is_empty()
+            && let Some(key) = &cache_key
+            && let Some(cached) = cached_plan(world, root, key)
+        {
+            record_replan(world, root, Duration::ZERO);
+            let result = match cached {
+                Some(plan) => DecomposeResult::Success {
+                    sub_plan: plan,
+                    world_state,
+                },
+                None => DecomposeResult::Failure,
+            };
+            apply_decompose_result(world, root, &previous_mtr, result);
+            return Ok(());
+        }
         let ctx = DecomposeInput {
             world_state,
             planner: root,
@@ -124,54 +201,105 @@ fn update_plan_inner(
             previous_mtr: previous_mtr.clone(),
             conditions: initial_conditions,
         };
-        let result = world.run_system_with(compound_task.decompose, ctx)?;
+        let start = Instant::now();
+        let result = compound_task.decompose(world, ctx)?;
         world.flush();
-
-        match result {
-            DecomposeResult::Success { sub_plan: plan, .. } => {
-                if previous_mtr == plan.mtr
-                    && world.entity(root).get::<Plan>().is_some_and(|prev_plan| {
-                        prev_plan.nodes.len() == plan.nodes.len()
-                            && prev_plan
-                                .nodes
-                                .iter()
-                                .zip(plan.nodes.iter())
-                                .all(|(a, b)| a.entity == b.entity)
-                    })
-                {
-                    // We found the same plan we are already running. Just keep that one.
-                    return Ok(());
+        record_replan(world, root, start.elapsed());
+        if let Some(key) = cache_key {
+            match &result {
+                DecomposeResult::Success { sub_plan, .. } => {
+                    cache_plan(world, root, key, Some(sub_plan));
                 }
-                plan
+                DecomposeResult::Failure => cache_plan(world, root, key, None),
+                DecomposeResult::Rejection => {}
             }
-            DecomposeResult::Failure => Plan::default(),
-            DecomposeResult::Rejection => return Ok(()),
         }
+        apply_decompose_result(world, root, &previous_mtr, result);
     } else {
         unreachable!(
             "Bevy should guarantee that `AnyOf` contains at least one element that is `Some`"
         )
+    }
+    Ok(())
+}
+
+/// The [`Mtr`] of the plan `root` is currently running.
+pub(crate) fn previous_mtr(world: &World, root: Entity) -> Mtr {
+    if let Some(plan) = world.entity(root).get::<Plan>() {
+        plan.mtr.clone()
+    } else {
+        Mtr::none()
+    }
+}
+
+/// Inserts the plan found by decomposing the domain of `root`, unless it is the plan that is already running.
+pub(crate) fn apply_decompose_result(
+    world: &mut World,
+    root: Entity,
+    previous_mtr: &Mtr,
+    result: DecomposeResult,
+) {
+    let plan = match result {
+        DecomposeResult::Success { sub_plan: plan, .. } => {
+            if *previous_mtr == plan.mtr
+                && world.entity(root).get::<Plan>().is_some_and(|prev_plan| {
+                    prev_plan.nodes.len() == plan.nodes.len()
+                        && prev_plan
+                            .nodes
+                            .iter()
+                            .zip(plan.nodes.iter())
+                            .all(|(a, b)| a.entity == b.entity)
+                })
+            {
+                // We found the same plan we are already running. Just keep that one.
+                return;
+            }
+            plan
+        }
+        DecomposeResult::Failure => {
+            end_delegation(world, root, false);
+            Plan::default()
+        }
+        DecomposeResult::Rejection => return,
     };
+    insert_plan(world, root, plan);
+}
 
-    if !plan.is_empty()
+/// Replaces the plan of `root`, adding the effects of `root` itself to the last operator.
+pub(crate) fn insert_plan(world: &mut World, root: Entity, mut plan: Plan) {
+    if let Some(&idx) = plan.back()
         && let Some(effect_relations) = world.get::<Effects>(root)
     {
-        for effect in effects.iter_many(world, effect_relations) {
-            let idx = *plan.back().unwrap();
-            plan.nodes[idx].effects.push(effect);
-        }
+        let effects: Vec<_> = effect_relations
+            .iter()
+            .filter(|&effect| world.entity(effect).contains::<Effect>())
+            .collect();
+        plan.nodes[idx].effects.extend(effects);
     }
+    replace_plan(world, root, plan);
+}
 
+/// Inserts `plan` as is, claiming its [`Reserve`]s, recording it in the [`PlanHistory`] and triggering [`ReplacePlan`].
+/// Returns `false` and keeps the current plan if another agent holds one of the reservations.
+pub(crate) fn replace_plan(world: &mut World, root: Entity, mut plan: Plan) -> bool {
+    let reservations = plan_reservations(world, &plan);
+    if let Some(key) = reserved_by_other(world, root, &reservations) {
+        debug!(?root, %key, "reservation is held by another agent, keeping the current plan");
+        return false;
+    }
+    plan.update_track();
     let old_plan = world
         .entity(root)
         .get::<Plan>()
         .cloned()
         .unwrap_or_default();
+    record_plan_replaced(world, root, &plan);
     world.entity_mut(root).insert(plan);
+    claim_reservations(world, root, reservations);
     world.trigger(ReplacePlan {
         entity: root,
         old: old_plan,
         _pd: PhantomData,
     });
-    Ok(())
+    true
 }
@@ -0,0 +1,238 @@
+//! Deterministic record and replay of agent decisions. Requires the `record` feature.
+//!
+//! Insert [`DecisionRecording`] as a resource and [`RecordDecisions`] on the agents of interest to start recording.
+//! The recording contains the [`Props`] each agent planned with and the [`OperatorStatus`] of every operator run,
+//! and can be saved to a RON file with [`DecisionRecording::save`].
+//!
+//! To replay it, load it with [`DecisionRecording::load`] and insert it as [`DecisionReplay`].
+//! While replaying, agents with a matching recording plan with the recorded [`Props`], and their operators
+//! are not run at all. Instead, the recorded statuses are returned, so the same plans are rebuilt.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{plan::target::props_source, prelude::*};
+
+/// Marks an agent holding a [`Plan`] to be recorded into the [`DecisionRecording`].
+#[derive(Component, Debug, Default, Clone, Copy, Reflect)]
+#[reflect(Component)]
+pub struct RecordDecisions;
+
+/// The decisions of all agents marked with [`RecordDecisions`]. Recording happens while this resource exists.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecisionRecording {
+    /// The recordings of the individual agents.
+    pub agents: Vec<AgentRecording>,
+}
+
+impl DecisionRecording {
+    /// Serializes the recording into a RON file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, ron)?;
+        Ok(())
+    }
+
+    /// Deserializes a recording from a RON file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let ron = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&ron)?)
+    }
+
+    fn agent_mut(&mut self, agent: String) -> &mut AgentRecording {
+        let index = match self.agents.iter().position(|a| a.agent == agent) {
+            Some(index) => index,
+            None => {
+                self.agents.push(AgentRecording {
+                    agent,
+                    ..Default::default()
+                });
+                self.agents.len() - 1
+            }
+        };
+        &mut self.agents[index]
+    }
+}
+
+/// The recorded decisions of a single agent.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentRecording {
+    /// The [`Name`] of the agent, or its [`Entity`] if it has no name. Used to match agents when replaying,
+    /// so recorded agents should have unique names.
+    pub agent: String,
+    /// The [`Props`] at the start of every replan, in order.
+    pub replans: Vec<Vec<(String, RecordedValue)>>,
+    /// Every operator run, in order.
+    pub operator_runs: Vec<RecordedOperatorRun>,
+}
+
+/// A single operator run in an [`AgentRecording`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedOperatorRun {
+    /// The [`Name`] of the operator, or its [`Entity`] if it has no name.
+    pub operator: String,
+    /// What the operator returned. Errors when running the operator system are recorded as [`OperatorStatus::Failure`].
+    pub status: OperatorStatus,
+}
+
+/// A serializable version of a [`Value`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecordedValue {
+    /// A boolean prop.
+    Bool(bool),
+    /// A numeric prop.
+    Float(f32),
+    /// A string prop.
+    Str(String),
+}
+
+impl From<Value> for RecordedValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(value) => Self::Bool(value),
+            Value::Float(value) => Self::Float(value),
+            Value::Str(value) => Self::Str(value.to_string()),
+        }
+    }
+}
+
+impl From<&RecordedValue> for Value {
+    fn from(value: &RecordedValue) -> Self {
+        match value {
+            RecordedValue::Bool(value) => Value::from(*value),
+            RecordedValue::Float(value) => Value::from(*value),
+            RecordedValue::Str(value) => Value::from(value.as_str()),
+        }
+    }
+}
+
+/// A [`DecisionRecording`] that is currently being replayed.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct DecisionReplay {
+    recording: DecisionRecording,
+    cursors: Vec<ReplayCursor>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ReplayCursor {
+    replan: usize,
+    operator_run: usize,
+}
+
+impl DecisionReplay {
+    /// Starts replaying the given recording from the beginning.
+    pub fn new(recording: DecisionRecording) -> Self {
+        Self {
+            cursors: vec![ReplayCursor::default(); recording.agents.len()],
+            recording,
+        }
+    }
+
+    /// Returns whether all recorded replans and operator runs were replayed.
+    pub fn is_finished(&self) -> bool {
+        self.recording
+            .agents
+            .iter()
+            .zip(&self.cursors)
+            .all(|(agent, cursor)| {
+                cursor.replan >= agent.replans.len()
+                    && cursor.operator_run >= agent.operator_runs.len()
+            })
+    }
+
+    fn agent(&self, agent: &str) -> Option<usize> {
+        self.recording.agents.iter().position(|a| a.agent == agent)
+    }
+}
+
+fn recording_name(world: &World, entity: Entity) -> String {
+    world
+        .get::<Name>(entity)
+        .map_or_else(|| entity.to_string(), ToString::to_string)
+}
+
+/// Called at the start of every replan. Records the [`Props`] `entity` plans with, or restores them when replaying.
+pub(crate) fn record_replan(world: &mut World, entity: Entity) {
+    let agent = recording_name(world, entity);
+    let source = props_source(world, entity);
+    if let Some(mut replay) = world.get_resource_mut::<DecisionReplay>()
+        && let Some(index) = replay.agent(&agent)
+    {
+        let cursor = replay.cursors[index].replan;
+        let Some(snapshot) = replay.recording.agents[index].replans.get(cursor).cloned() else {
+            warn!(?entity, %agent, "replay has no more recorded replans for agent");
+            return;
+        };
+        replay.cursors[index].replan += 1;
+        let mut source = world.entity_mut(source);
+        for (name, value) in &snapshot {
+            source.set_prop(name.as_str(), Value::from(value));
+        }
+        return;
+    }
+    if !world.entity(entity).contains::<RecordDecisions>() {
+        return;
+    }
+    let Some(props) = world.get::<Props>(source) else {
+        return;
+    };
+    let mut snapshot: Vec<_> = props
+        .iter()
+        .map(|(name, value)| (name.to_string(), RecordedValue::from(*value)))
+        .collect();
+    snapshot.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if let Some(mut recording) = world.get_resource_mut::<DecisionRecording>() {
+        recording.agent_mut(agent).replans.push(snapshot);
+    }
+}
+
+/// Returns the recorded status of `operator` if `entity` is being replayed.
+pub(crate) fn replayed_status(
+    world: &mut World,
+    entity: Entity,
+    operator: Entity,
+) -> Option<OperatorStatus> {
+    let agent = recording_name(world, entity);
+    let operator_name = recording_name(world, operator);
+    let mut replay = world.get_resource_mut::<DecisionReplay>()?;
+    let index = replay.agent(&agent)?;
+    let cursor = replay.cursors[index].operator_run;
+    let Some(run) = replay.recording.agents[index].operator_runs.get(cursor) else {
+        warn!(?entity, %agent, "replay has no more recorded operator runs for agent");
+        return Some(OperatorStatus::Failure);
+    };
+    if run.operator != operator_name {
+        warn!(
+            ?entity,
+            %agent,
+            expected = %run.operator,
+            actual = %operator_name,
+            "replay diverged from recording"
+        );
+    }
+    let status = run.status;
+    replay.cursors[index].operator_run += 1;
+    Some(status)
+}
+
+/// Records the status `operator` of `entity` returned.
+pub(crate) fn record_operator_status(
+    world: &mut World,
+    entity: Entity,
+    operator: Entity,
+    status: OperatorStatus,
+) {
+    if !world.entity(entity).contains::<RecordDecisions>()
+        || !world.contains_resource::<DecisionRecording>()
+    {
+        return;
+    }
+    let agent = recording_name(world, entity);
+    let operator = recording_name(world, operator);
+    world
+        .resource_mut::<DecisionRecording>()
+        .agent_mut(agent)
+        .operator_runs
+        .push(RecordedOperatorRun { operator, status });
+}
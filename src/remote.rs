@@ -0,0 +1,331 @@
+//! Methods for inspecting and steering planners over the [Bevy Remote Protocol](bevy_remote).
+//! Add [`BaeRemotePlugin`] next to [`RemotePlugin`](bevy_remote::RemotePlugin) to enable them.
+
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_reflect::serde::TypedReflectSerializer;
+use bevy_remote::{
+    BrpError, BrpResult, RemoteMethodSystemId, RemoteMethods, builtin_methods::parse_some,
+    error_codes,
+};
+use serde::Deserialize;
+use serde_json::{Map, Number, Value as JsonValue, json};
+
+use crate::{
+    plan::{TaskNode, history::PlanHistory, mtr::Mtr, target::props_source},
+    prelude::*,
+    task::compound::TypeErasedCompoundTask,
+};
+
+/// The method path for a `bae.list_agents` request.
+/// Returns all entities holding a [`Plan`].
+pub const BRP_BAE_LIST_AGENTS_METHOD: &str = "bae.list_agents";
+
+/// The method path for a `bae.get_agent` request.
+/// Returns the [`Plan`], [`Props`] and domain tree of an agent.
+/// The props are the ones the agent plans with, see [`PlanTarget`].
+pub const BRP_BAE_GET_AGENT_METHOD: &str = "bae.get_agent";
+
+/// The method path for a `bae.set_prop` request.
+/// Sets a single prop of an agent to a boolean, number or string.
+/// Like [`bae.get_agent`](BRP_BAE_GET_AGENT_METHOD), this uses the props the agent plans with.
+/// Numbers that cannot be represented exactly as a 32-bit float are rejected.
+pub const BRP_BAE_SET_PROP_METHOD: &str = "bae.set_prop";
+
+/// The method path for a `bae.update_plan` request.
+/// Triggers [`UpdatePlan`] on an agent.
+pub const BRP_BAE_UPDATE_PLAN_METHOD: &str = "bae.update_plan";
+
+/// The method path for a `bae.clear_plan` request.
+/// Clears the [`Plan`] of an agent, forcing a full replan.
+pub const BRP_BAE_CLEAR_PLAN_METHOD: &str = "bae.clear_plan";
+
+/// The method path for a `bae.log_plan` request.
+/// Triggers [`LogPlan`] on an agent.
+pub const BRP_BAE_LOG_PLAN_METHOD: &str = "bae.log_plan";
+
+/// Registers the `bae.*` methods with the [`RemotePlugin`](bevy_remote::RemotePlugin).
+/// The [`RemotePlugin`](bevy_remote::RemotePlugin) must be added as well, but the order does not matter.
+#[derive(Default)]
+pub struct BaeRemotePlugin;
+
+impl Plugin for BaeRemotePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Plan>()
+            .register_type::<TaskNode>()
+            .register_type::<Mtr>()
+            .register_type::<PlanHistory>()
+            .register_type::<Tasks>()
+            .register_type::<TaskOf>()
+            .register_type::<Conditions>()
+            .register_type::<ConditionOf>()
+            .register_type::<Effects>()
+            .register_type::<EffectOf>();
+    }
+
+    fn finish(&self, app: &mut App) {
+        let world = app.world_mut();
+        let methods = [
+            (
+                BRP_BAE_LIST_AGENTS_METHOD,
+                world.register_system(process_list_agents_request),
+            ),
+            (
+                BRP_BAE_GET_AGENT_METHOD,
+                world.register_system(process_get_agent_request),
+            ),
+            (
+                BRP_BAE_SET_PROP_METHOD,
+                world.register_system(process_set_prop_request),
+            ),
+            (
+                BRP_BAE_UPDATE_PLAN_METHOD,
+                world.register_system(process_update_plan_request),
+            ),
+            (
+                BRP_BAE_CLEAR_PLAN_METHOD,
+                world.register_system(process_clear_plan_request),
+            ),
+            (
+                BRP_BAE_LOG_PLAN_METHOD,
+                world.register_system(process_log_plan_request),
+            ),
+        ];
+        let mut remote_methods = world.get_resource_or_init::<RemoteMethods>();
+        for (name, system_id) in methods {
+            remote_methods.insert(name, RemoteMethodSystemId::Instant(system_id));
+        }
+    }
+}
+
+/// `params` of all `bae.*` requests that target a single agent.
+#[derive(Debug, Deserialize)]
+pub struct BrpBaeAgentParams {
+    /// The entity holding the [`Plan`].
+    pub entity: Entity,
+}
+
+/// `params` of a `bae.set_prop` request.
+#[derive(Debug, Deserialize)]
+pub struct BrpBaeSetPropParams {
+    /// The entity holding the [`Plan`].
+    pub entity: Entity,
+    /// The name of the prop to set.
+    pub name: String,
+    /// The new value. Must be a boolean, a number that is exactly representable as a 32-bit float, or a string.
+    pub value: JsonValue,
+}
+
+/// Handles a `bae.list_agents` request coming from a client.
+pub fn process_list_agents_request(
+    In(_params): In<Option<JsonValue>>,
+    agents: Query<NameOrEntity, With<Plan>>,
+) -> BrpResult {
+    let agents = agents
+        .iter()
+        .map(|name| {
+            json!({
+                "entity": name.entity,
+                "name": name.name.map(Name::as_str),
+            })
+        })
+        .collect();
+    Ok(JsonValue::Array(agents))
+}
+
+/// Handles a `bae.get_agent` request coming from a client.
+pub fn process_get_agent_request(In(params): In<Option<JsonValue>>, world: &World) -> BrpResult {
+    let BrpBaeAgentParams { entity } = parse_some(params)?;
+    let plan = get_plan(world, entity)?;
+    let registry = world.resource::<AppTypeRegistry>().read();
+    let serialized_plan = serde_json::to_value(TypedReflectSerializer::new(
+        plan.as_partial_reflect(),
+        &registry,
+    ))
+    .map_err(BrpError::component_error)?;
+    let operators_left: Vec<_> = plan
+        .operators_left
+        .iter()
+        .map(|&idx| plan.nodes[idx].entity)
+        .collect();
+    let props: Map<String, JsonValue> = world
+        .get::<Props>(props_source(world, entity))
+        .into_iter()
+        .flat_map(|props| props.iter())
+        .map(|(name, &value)| (name.to_string(), value_json(value)))
+        .collect();
+    Ok(json!({
+        "plan": serialized_plan,
+        "operators_left": operators_left,
+        "props": props,
+        "domain": domain_tree(world, entity),
+    }))
+}
+
+/// Handles a `bae.set_prop` request coming from a client.
+pub fn process_set_prop_request(In(params): In<Option<JsonValue>>, world: &mut World) -> BrpResult {
+    let BrpBaeSetPropParams {
+        entity,
+        name,
+        value,
+    } = parse_some(params)?;
+    get_plan(world, entity)?;
+    let value = match value {
+        JsonValue::Bool(value) => Value::from(value),
+        JsonValue::Number(value) => match exact_f32(&value) {
+            Some(value) => Value::from(value),
+            None => {
+                return Err(invalid_params(format!(
+                    "{value} cannot be represented exactly as a 32-bit float"
+                )));
+            }
+        },
+        JsonValue::String(value) => Value::from(value.as_str()),
+        value => {
+            return Err(invalid_params(format!(
+                "{value} is not a boolean, number or string"
+            )));
+        }
+    };
+    let source = props_source(world, entity);
+    world.entity_mut(source).set_prop(name.as_str(), value);
+    Ok(JsonValue::Null)
+}
+
+/// Handles a `bae.update_plan` request coming from a client.
+pub fn process_update_plan_request(
+    In(params): In<Option<JsonValue>>,
+    world: &mut World,
+) -> BrpResult {
+    let BrpBaeAgentParams { entity } = parse_some(params)?;
+    get_plan(world, entity)?;
+    world.trigger(UpdatePlan::new(entity));
+    Ok(JsonValue::Null)
+}
+
+/// Handles a `bae.clear_plan` request coming from a client.
+pub fn process_clear_plan_request(
+    In(params): In<Option<JsonValue>>,
+    world: &mut World,
+) -> BrpResult {
+    let BrpBaeAgentParams { entity } = parse_some(params)?;
+    get_plan(world, entity)?;
+    world.entity_mut(entity).insert(Plan::new());
+    Ok(JsonValue::Null)
+}
+
+/// Handles a `bae.log_plan` request coming from a client.
+pub fn process_log_plan_request(In(params): In<Option<JsonValue>>, world: &mut World) -> BrpResult {
+    let BrpBaeAgentParams { entity } = parse_some(params)?;
+    get_plan(world, entity)?;
+    world.trigger(LogPlan::new(entity));
+    Ok(JsonValue::Null)
+}
+
+fn get_plan(world: &World, entity: Entity) -> Result<&Plan, BrpError> {
+    world
+        .get_entity(entity)
+        .map_err(|_| BrpError::entity_not_found(entity))?
+        .get::<Plan>()
+        .ok_or_else(|| BrpError::component_not_present("bevy_bae::plan::Plan", entity))
+}
+
+fn invalid_params(message: String) -> BrpError {
+    BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message,
+        data: None,
+    }
+}
+
+/// Converts a prop into the matching JSON type.
+fn value_json(value: Value) -> JsonValue {
+    match value {
+        Value::Bool(value) => value.into(),
+        Value::Float(value) => {
+            Number::from_f64(f64::from(value)).map_or(JsonValue::Null, JsonValue::Number)
+        }
+        Value::Str(value) => value.as_str().into(),
+    }
+}
+
+/// Returns `number` as a prop, unless that would lose precision.
+fn exact_f32(number: &Number) -> Option<f32> {
+    if let Some(int) = number.as_i64() {
+        let float = int as f32;
+        return (float as i128 == i128::from(int)).then_some(float);
+    }
+    if let Some(int) = number.as_u64() {
+        let float = int as f32;
+        return (float as i128 == i128::from(int)).then_some(float);
+    }
+    let value = number.as_f64()?;
+    let float = value as f32;
+    (f64::from(float) == value).then_some(float)
+}
+
+/// Recursively describes the task tree starting at `task`.
+/// Follows [`TaskRef`]s and lists the [`SmartObject`]s a [`SmartObjectSlot`] may decompose into,
+/// but stops at tasks that are already being described further up, as domains may be recursive.
+fn domain_tree(world: &World, task: Entity) -> JsonValue {
+    domain_subtree(world, task, &mut Vec::new())
+}
+
+fn domain_subtree(world: &World, task: Entity, ancestors: &mut Vec<Entity>) -> JsonValue {
+    let Ok(entity) = world.get_entity(task) else {
+        return JsonValue::Null;
+    };
+    if ancestors.contains(&task) {
+        let mut tree = entity_json(world, task);
+        tree["recursive"] = true.into();
+        return tree;
+    }
+    let names = |entities: Option<&[Entity]>| -> Vec<JsonValue> {
+        entities
+            .unwrap_or_default()
+            .iter()
+            .map(|&entity| entity_json(world, entity))
+            .collect()
+    };
+    let kind = if entity.contains::<Operator>() {
+        "operator"
+    } else if entity.contains::<TaskRef>() {
+        "reference"
+    } else if entity.contains::<Slot>() {
+        "slot"
+    } else if entity.contains::<SmartObjectSlot>() {
+        "smart_object_slot"
+    } else if entity.contains::<TypeErasedCompoundTask>() {
+        "compound"
+    } else {
+        "none"
+    };
+    let mut tree = entity_json(world, task);
+    tree["kind"] = kind.into();
+    tree["conditions"] = names(entity.get::<Conditions>().map(|c| c.as_slice())).into();
+    tree["effects"] = names(entity.get::<Effects>().map(|e| e.as_slice())).into();
+    // The plugged task of a `Slot` is its only subtask
+    let mut subtasks: Vec<_> = entity.get::<Tasks>().into_iter().flatten().collect();
+    if let Some(&TaskRef(target)) = entity.get::<TaskRef>() {
+        subtasks.push(target);
+    }
+    if entity.contains::<SmartObjectSlot>()
+        && let Some(mut objects) = world.try_query_filtered::<Entity, With<SmartObject>>()
+    {
+        subtasks.extend(objects.iter(world));
+    }
+    ancestors.push(task);
+    tree["tasks"] = subtasks
+        .into_iter()
+        .map(|subtask| domain_subtree(world, subtask, ancestors))
+        .collect::<Vec<_>>()
+        .into();
+    ancestors.pop();
+    tree
+}
+
+fn entity_json(world: &World, entity: Entity) -> JsonValue {
+    json!({
+        "entity": entity,
+        "name": world.get::<Name>(entity).map(Name::as_str),
+    })
+}
@@ -0,0 +1,185 @@
+//! Contains batched [`Operator`]s, which are executed by regular systems for all agents at once.
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use bevy_bae::{prelude::*, task::batched::*};
+//!
+//! struct Walk;
+//!
+//! fn walk(mut agents: Query<(&mut Transform, &mut Active<Walk>)>) {
+//!     agents.par_iter_mut().for_each(|(mut transform, mut active)| {
+//!         transform.translation.x += 1.0;
+//!         if transform.translation.x >= 10.0 {
+//!             active.status = OperatorStatus::Success;
+//!         }
+//!     });
+//! }
+//!
+//! App::new()
+//!     .add_plugins((MinimalPlugins, BaePlugin::default()))
+//!     .add_batched_operator::<Walk>()
+//!     .add_systems(FixedUpdate, walk.before(BaeSystems::ExecutePlan));
+//!
+//! let behavior = (Sequence, tasks![(Name::new("walk"), Operator::batched::<Walk>())]);
+//! ```
+
+use core::marker::PhantomData;
+
+use bevy_ecs::{intern::Interned, schedule::ScheduleLabel};
+
+use crate::{plan::execution::execute_plan, prelude::*};
+
+/// Inserted on the actor of an agent while its [`Plan`] is in an [`Operator::batched`] operator of type `M`.
+/// Query it in a regular system to run the operator for all agents at once, and set [`Active::status`]
+/// to report back when the agent is done. The marker is removed once the status was picked up or the plan changed.
+#[derive(Component, Debug)]
+pub struct Active<M: Send + Sync + 'static> {
+    /// The entity holding the [`Plan`].
+    pub planner: Entity,
+    /// The entity holding the [`Operator`] that is running.
+    pub operator: Entity,
+    /// What the operator returns on its next run. Starts out as [`OperatorStatus::Ongoing`].
+    pub status: OperatorStatus,
+    marker: PhantomData<fn() -> M>,
+}
+
+impl<M: Send + Sync + 'static> Active<M> {
+    /// Creates a new marker for the given planner and operator entity.
+    pub fn new(planner: Entity, operator: Entity) -> Self {
+        Self {
+            planner,
+            operator,
+            status: OperatorStatus::Ongoing,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Inserts [`Active`] of the right type on the actor of a planner.
+pub(crate) type StartBatched = fn(&mut World, Entity, Entity, Entity);
+
+impl Operator {
+    /// Creates an operator that inserts [`Active<M>`] on the actor and stays [`OperatorStatus::Ongoing`]
+    /// until a system changes [`Active::status`]. The system of the operator is never run.
+    /// You must also call [`BatchedAppExt::add_batched_operator`] for `M`.
+    pub fn batched<M: Send + Sync + 'static>() -> Self {
+        Self {
+            start_batched: Some(start_batched::<M>),
+            ..Self::shared(batched_system::<M>)
+        }
+    }
+}
+
+/// Placeholder system of [`Operator::batched`], generic so that each `M` gets its own shared system.
+fn batched_system<M: Send + Sync + 'static>(_: In<OperatorInput>) -> OperatorStatus {
+    OperatorStatus::Ongoing
+}
+
+fn start_batched<M: Send + Sync + 'static>(
+    world: &mut World,
+    actor: Entity,
+    planner: Entity,
+    operator: Entity,
+) {
+    if let Ok(mut actor) = world.get_entity_mut(actor) {
+        actor.insert(Active::<M>::new(planner, operator));
+    }
+}
+
+/// The status of the batched operator a planner is in, as last reported by its [`Active`].
+#[derive(Component, Debug)]
+pub(crate) struct BatchedRun {
+    operator: Entity,
+    actor: Entity,
+    status: OperatorStatus,
+}
+
+/// Runs a batched operator for `execute_plan`: starts it if the planner isn't in it yet,
+/// and otherwise returns the status that was last polled.
+pub(crate) fn run_batched(
+    world: &mut World,
+    input: &OperatorInput,
+    start: StartBatched,
+) -> OperatorStatus {
+    if let Some(run) = world.get::<BatchedRun>(input.entity)
+        && run.operator == input.operator
+    {
+        let status = run.status;
+        if status != OperatorStatus::Ongoing {
+            world.entity_mut(input.entity).remove::<BatchedRun>();
+        }
+        return status;
+    }
+    start(world, input.actor, input.entity, input.operator);
+    world.entity_mut(input.entity).insert(BatchedRun {
+        operator: input.operator,
+        actor: input.actor,
+        status: OperatorStatus::Ongoing,
+    });
+    OperatorStatus::Ongoing
+}
+
+/// The schedule of the [`BaePlugin`], so that batched operators can be polled in it.
+#[derive(Resource)]
+pub(crate) struct BaeSchedule(pub(crate) Interned<dyn ScheduleLabel>);
+
+/// Used to allow calling [`BatchedAppExt::add_batched_operator`] on [`App`].
+pub trait BatchedAppExt {
+    /// Initializes [`Operator::batched`] operators of type `M`. Polls [`Active<M>`] right before plans are executed,
+    /// and makes sure it is removed when the plan of an agent changes. Panics if called before adding the [`BaePlugin`].
+    fn add_batched_operator<M: Send + Sync + 'static>(&mut self) -> &mut Self;
+}
+
+impl BatchedAppExt for App {
+    fn add_batched_operator<M: Send + Sync + 'static>(&mut self) -> &mut Self {
+        let schedule = self
+            .world()
+            .get_resource::<BaeSchedule>()
+            .expect("add `BaePlugin` before `add_batched_operator`")
+            .0;
+        self.add_systems(
+            schedule,
+            poll_batched::<M>
+                .in_set(BaeSystems::ExecutePlan)
+                .before(execute_plan),
+        )
+        .add_observer(remove_active_on_replace::<M>);
+        self
+    }
+}
+
+fn poll_batched<M: Send + Sync + 'static>(
+    active: Query<(Entity, &Active<M>)>,
+    mut runs: Query<&mut BatchedRun>,
+    mut commands: Commands,
+) {
+    for (actor, active) in &active {
+        if active.status == OperatorStatus::Ongoing {
+            continue;
+        }
+        if let Ok(mut run) = runs.get_mut(active.planner)
+            && run.operator == active.operator
+        {
+            run.status = active.status;
+        }
+        commands.entity(actor).try_remove::<Active<M>>();
+    }
+}
+
+fn remove_active_on_replace<M: Send + Sync + 'static>(
+    replace: On<Replace, Plan>,
+    runs: Query<&BatchedRun>,
+    active: Query<&Active<M>>,
+    mut commands: Commands,
+) {
+    let Ok(run) = runs.get(replace.entity) else {
+        return;
+    };
+    if active
+        .get(run.actor)
+        .is_ok_and(|active| active.planner == replace.entity)
+    {
+        commands.entity(run.actor).try_remove::<Active<M>>();
+    }
+    commands.entity(replace.entity).try_remove::<BatchedRun>();
+}
@@ -1,20 +1,38 @@
 //! Contains types representing a tree of more compound tasks, where the leaves are [`Operator`]s
 
-use bevy_ecs::system::SystemId;
+use alloc::sync::Arc;
+use core::{
+    any::TypeId,
+    ops::{Deref, DerefMut},
+};
+
+use bevy_ecs::system::{RegisteredSystemError, SystemId};
+use bevy_platform::collections::{HashMap, HashSet};
 
 use crate::{
-    plan::{Plan, mtr::Mtr},
+    diagnostics::count_visited_task,
+    plan::{
+        Plan, TaskNode,
+        mtr::Mtr,
+        reservation::{ReservationKey, is_key_reserved_by_other, is_reserved_by_other},
+    },
     prelude::*,
 };
 
+pub mod reference;
 pub mod relationship;
+pub mod repeat;
 pub mod select;
 pub mod sequence;
+pub mod slot;
+pub mod smart_object;
 
 /// Trait implemented for compound tasks. The builtin [`CompoundTask`]s are [`Sequence`] and [`Select`].
 /// If you implement this trait, you must also call [`CompoundAppExt::add_compound_task`] to initialize it.
 pub trait CompoundTask: Component {
     /// Registers the decomposition system for this compound task.
+    /// All entities holding this compound task share the same system, which is registered when the first one is spawned
+    /// and unregistered when the last one is removed. Nested decompositions of the same type register additional instances.
     fn register_decompose(commands: &mut Commands) -> DecomposeId;
 }
 
@@ -30,7 +48,11 @@ pub struct DecomposeInput {
     pub compound_task: Entity,
     /// The current [`Props`] associated with this step of the decomposition.
     /// Expected to be mutated during decomposition and returned in [`DecomposeResult`].
-    pub world_state: Props,
+    /// Cloning it to hand it to a nested task is cheap, see [`WorldState`].
+    ///
+    /// [`WorldState`] dereferences to [`Props`], so conditions and effects can be used on it as before.
+    /// Use [`WorldState::from`] and [`Props::from`] to convert between the two.
+    pub world_state: WorldState,
     /// The [`Mtr`] of the previous plan.
     /// Used to determine whether the current decomposition should return [`DecomposeResult::Rejection`] because it has a lower priority than the running task.
     pub previous_mtr: Mtr,
@@ -39,16 +61,210 @@ pub struct DecomposeInput {
     pub conditions: Vec<Entity>,
 }
 
+/// The [`Props`] used during decomposition, dereferencing to [`Props`].
+///
+/// Clones share the same props and only copy them once one of them is mutated while the others are still alive.
+/// This makes trying out a branch cheap: hand a clone to it, and if the branch fails, drop the clone to roll back its changes.
+#[derive(Debug, Clone, Default)]
+pub struct WorldState(Arc<Props>);
+
+impl WorldState {
+    /// Creates a new world state from the given props.
+    pub fn new(props: Props) -> Self {
+        Self(Arc::new(props))
+    }
+
+    /// Returns the props.
+    pub fn props(&self) -> &Props {
+        &self.0
+    }
+
+    /// Returns the props mutably, copying them first if they are still shared with another clone.
+    pub fn make_mut(&mut self) -> &mut Props {
+        Arc::make_mut(&mut self.0)
+    }
+
+    /// Returns the props, copying them only if they are still shared with another clone.
+    pub fn into_props(self) -> Props {
+        Arc::unwrap_or_clone(self.0)
+    }
+}
+
+impl From<Props> for WorldState {
+    fn from(props: Props) -> Self {
+        Self::new(props)
+    }
+}
+
+impl From<WorldState> for Props {
+    fn from(world_state: WorldState) -> Self {
+        world_state.into_props()
+    }
+}
+
+impl Deref for WorldState {
+    type Target = Props;
+
+    fn deref(&self) -> &Props {
+        &self.0
+    }
+}
+
+impl DerefMut for WorldState {
+    fn deref_mut(&mut self) -> &mut Props {
+        self.make_mut()
+    }
+}
+
+/// How often a compound task may be entered again while it is still being decomposed, e.g. through a [`TaskRef`]
+/// to one of its ancestors.
+/// Insert this on the entity holding the [`Plan`] to configure it for that agent, otherwise the default of 32 is used.
+/// As every level of recursion takes up space on the call stack, values above [`MaxRecursionDepth::MAX`] are clamped to it.
+///
+/// Decompositions exceeding the limit fail and log the offending cycle.
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct MaxRecursionDepth(pub usize);
+
+impl Default for MaxRecursionDepth {
+    fn default() -> Self {
+        Self(32)
+    }
+}
+
+impl MaxRecursionDepth {
+    /// The hard cap on the recursion depth.
+    pub const MAX: usize = 256;
+
+    /// Returns the limit configured for `planner`.
+    pub(crate) fn of(world: &World, planner: Entity) -> usize {
+        world
+            .get::<MaxRecursionDepth>(planner)
+            .copied()
+            .unwrap_or_default()
+            .0
+            .min(Self::MAX)
+    }
+}
+
+/// The compound tasks that are currently being decomposed, innermost last.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct DecomposeStack(Vec<Entity>);
+
 #[derive(Component, Clone)]
 pub(crate) struct TypeErasedCompoundTask {
+    type_id: TypeId,
     pub(crate) decompose: DecomposeId,
 }
 
 impl TypeErasedCompoundTask {
     #[must_use]
-    fn new(id: DecomposeId) -> Self {
-        Self { decompose: id }
+    fn new<C: CompoundTask>(id: DecomposeId) -> Self {
+        Self {
+            type_id: TypeId::of::<C>(),
+            decompose: id,
+        }
+    }
+
+    /// Runs the shared decomposition system of this compound task.
+    /// If it is already running further up the stack, e.g. for a [`Select`] nested in a [`Select`],
+    /// another instance of the system is used instead, as one-shot systems cannot run recursively.
+    /// Fails if the compound task is already being decomposed [`MaxRecursionDepth`] times further up the stack.
+    pub(crate) fn decompose(
+        &self,
+        world: &mut World,
+        input: DecomposeInput,
+    ) -> Result<DecomposeResult, RegisteredSystemError<In<DecomposeInput>, DecomposeResult>> {
+        let task = input.compound_task;
+        let max_depth = MaxRecursionDepth::of(world, input.planner);
+        let stack = world.resource::<DecomposeStack>();
+        if stack.iter().filter(|&&entered| entered == task).count() >= max_depth {
+            let start = stack
+                .iter()
+                .rposition(|&entered| entered == task)
+                .unwrap_or(0);
+            let cycle: Vec<_> = stack[start..]
+                .iter()
+                .chain([&task])
+                .map(|&entity| match world.get::<Name>(entity) {
+                    Some(name) => format!("{entity} ({name})"),
+                    None => format!("{entity}"),
+                })
+                .collect();
+            warn!(
+                planner = ?input.planner,
+                "compound task was entered more than {max_depth} times while decomposing it, \
+                see `MaxRecursionDepth`: {}",
+                cycle.join(" -> ")
+            );
+            return Ok(DecomposeResult::Failure);
+        }
+        world.resource_mut::<DecomposeStack>().push(task);
+        let mut result = self.run_decompose(world, input);
+        world.resource_mut::<DecomposeStack>().pop();
+        if let Ok(DecomposeResult::Success { sub_plan, .. }) = &mut result {
+            sub_plan.enclose(task);
+        }
+        result
     }
+
+    fn run_decompose(
+        &self,
+        world: &mut World,
+        input: DecomposeInput,
+    ) -> Result<DecomposeResult, RegisteredSystemError<In<DecomposeInput>, DecomposeResult>> {
+        let not_registered = RegisteredSystemError::SystemIdNotRegistered(self.decompose);
+        let mut systems = world.resource_mut::<DecomposeSystems>();
+        let Some(pool) = systems.get_mut(&self.type_id) else {
+            return Err(not_registered);
+        };
+        let depth = pool.running;
+        pool.running += 1;
+        let id = match pool.ids.get(depth) {
+            Some(&id) => id,
+            None => {
+                let register = pool.register;
+                let id = register(&mut world.commands());
+                world.flush();
+                if let Some(pool) = world
+                    .resource_mut::<DecomposeSystems>()
+                    .get_mut(&self.type_id)
+                {
+                    pool.ids.push(id);
+                }
+                id
+            }
+        };
+        let result = world.run_system_with(id, input);
+        let mut systems = world.resource_mut::<DecomposeSystems>();
+        if let Some(pool) = systems.get_mut(&self.type_id) {
+            pool.running -= 1;
+            // The last user may have been removed while the systems were running
+            if pool.users == 0
+                && pool.running == 0
+                && let Some(pool) = systems.remove(&self.type_id)
+            {
+                for id in pool.ids {
+                    world.unregister_system(id).ok();
+                }
+            }
+        }
+        result
+    }
+}
+
+/// The decomposition systems shared by all entities holding the same type of [`CompoundTask`].
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct DecomposeSystems(HashMap<TypeId, DecomposeSystemPool>);
+
+pub(crate) struct DecomposeSystemPool {
+    /// The first entry is the system shared by everyone, the rest are only used for nested decompositions.
+    ids: Vec<DecomposeId>,
+    /// How many of the systems are currently running.
+    running: usize,
+    /// How many entities currently hold this type of compound task.
+    users: usize,
+    register: fn(&mut Commands) -> DecomposeId,
 }
 
 /// The result of a decomposition attempt of a [`CompoundTask`].
@@ -58,7 +274,8 @@ pub enum DecomposeResult {
         /// The plan of this Decompose task.
         sub_plan: Plan,
         /// A modified copy of [`DecomposeInput::world_state`], updated with the decomposition.
-        world_state: Props,
+        /// Plain [`Props`] can be turned into it with [`Into::into`].
+        world_state: WorldState,
     },
     /// The decomposition would have resulted in a lower priority than the running task.
     Rejection,
@@ -67,6 +284,176 @@ pub enum DecomposeResult {
     Failure,
 }
 
+/// Read access to the tasks of a domain, so that the builtin [`CompoundTask`]s decompose the same way
+/// on the [`World`] and on a snapshot of it, see [`AsyncPlanning`](crate::plan::async_planning::AsyncPlanning).
+pub(crate) trait TaskSource {
+    /// The subtasks of `task` that are either an [`Operator`] or a [`CompoundTask`], in order.
+    fn subtasks(&self, task: Entity) -> Vec<Entity>;
+    /// Whether `task` is an [`Operator`].
+    fn is_operator(&self, task: Entity) -> bool;
+    /// The [`Condition`]s of `task`.
+    fn conditions(&self, task: Entity) -> Vec<(Entity, Condition)>;
+    /// The [`Effect`]s of `task`.
+    fn effects(&self, task: Entity) -> Vec<(Entity, Effect)>;
+    /// Whether `task` reserves something that another agent holds.
+    fn is_reserved_by_other(&self, task: Entity, planner: Entity) -> bool;
+    /// Whether `key` is held by an agent other than `planner`.
+    fn is_key_reserved_by_other(&self, key: ReservationKey, planner: Entity) -> bool;
+    /// Evaluates `condition` for `planner`, including [`Condition::not_reserved`].
+    fn condition_holds(&self, condition: &Condition, props: &Props, planner: Entity) -> bool {
+        condition.is_fullfilled_with(props, |key| self.is_key_reserved_by_other(key, planner))
+    }
+    /// Counts a task towards [`PlanDiagnostics::last_tasks_visited`](crate::diagnostics::PlanDiagnostics::last_tasks_visited).
+    fn count_visited_task(&mut self);
+    /// Decomposes the [`CompoundTask`] [`DecomposeInput::compound_task`].
+    fn decompose_compound(&mut self, input: DecomposeInput) -> DecomposeResult;
+}
+
+impl TaskSource for World {
+    fn subtasks(&self, task: Entity) -> Vec<Entity> {
+        self.get::<Tasks>(task)
+            .into_iter()
+            .flatten()
+            .filter(|&subtask| {
+                self.get_entity(subtask).is_ok_and(|subtask| {
+                    subtask.contains::<Operator>() || subtask.contains::<TypeErasedCompoundTask>()
+                })
+            })
+            .collect()
+    }
+
+    fn is_operator(&self, task: Entity) -> bool {
+        self.get::<Operator>(task).is_some()
+    }
+
+    fn conditions(&self, task: Entity) -> Vec<(Entity, Condition)> {
+        self.get::<Conditions>(task)
+            .into_iter()
+            .flatten()
+            .filter_map(|condition| Some((condition, self.get::<Condition>(condition)?.clone())))
+            .collect()
+    }
+
+    fn effects(&self, task: Entity) -> Vec<(Entity, Effect)> {
+        self.get::<Effects>(task)
+            .into_iter()
+            .flatten()
+            .filter_map(|effect| Some((effect, self.get::<Effect>(effect)?.clone())))
+            .collect()
+    }
+
+    fn is_reserved_by_other(&self, task: Entity, planner: Entity) -> bool {
+        is_reserved_by_other(self, task, planner)
+    }
+
+    fn is_key_reserved_by_other(&self, key: ReservationKey, planner: Entity) -> bool {
+        is_key_reserved_by_other(self, key, planner)
+    }
+
+    fn count_visited_task(&mut self) {
+        count_visited_task(self);
+    }
+
+    fn decompose_compound(&mut self, input: DecomposeInput) -> DecomposeResult {
+        let Some(compound_task) = self
+            .get::<TypeErasedCompoundTask>(input.compound_task)
+            .cloned()
+        else {
+            return DecomposeResult::Failure;
+        };
+        let result = compound_task.decompose(self, input);
+        self.flush();
+        result.unwrap_or(DecomposeResult::Failure)
+    }
+}
+
+/// Decomposes the single task `task` in place of [`DecomposeInput::compound_task`], as if it was inlined there.
+/// Checks the [`Condition`]s of `task`, decomposes it if it is a [`CompoundTask`], and applies its [`Effect`]s.
+/// The resulting [`Mtr`] is the one of `task`, so the caller adds no index of its own.
+pub(crate) fn decompose_subtask(
+    source: &mut impl TaskSource,
+    task: Entity,
+    mut ctx: DecomposeInput,
+) -> DecomposeResult {
+    let mut plan = Plan::new();
+    for (entity, condition) in source.conditions(task) {
+        if !source.condition_holds(&condition, &ctx.world_state, ctx.planner) {
+            return DecomposeResult::Failure;
+        }
+        ctx.conditions.push(entity);
+    }
+    if source.is_operator(task) {
+        let index = plan.add_node(TaskNode {
+            entity: task,
+            composite: false,
+            effects: vec![],
+            conditions: ctx.conditions,
+            parent: None,
+        });
+        plan.push_back(index);
+    } else {
+        let result = source.decompose_compound(DecomposeInput {
+            planner: ctx.planner,
+            compound_task: task,
+            world_state: ctx.world_state,
+            previous_mtr: ctx.previous_mtr,
+            conditions: ctx.conditions,
+        });
+        match result {
+            DecomposeResult::Success {
+                sub_plan,
+                world_state,
+            } => {
+                plan.mtr = sub_plan.mtr.clone();
+                plan.merge(sub_plan);
+                ctx.world_state = world_state;
+            }
+            result => return result,
+        }
+    }
+    if plan.is_empty() {
+        return DecomposeResult::Failure;
+    }
+    let idx = *plan.back().unwrap();
+    for (entity, effect) in source.effects(task) {
+        effect.apply(&mut ctx.world_state);
+        plan.nodes[idx].effects.push(entity);
+    }
+    DecomposeResult::Success {
+        sub_plan: plan,
+        world_state: ctx.world_state,
+    }
+}
+
+/// Returns all tasks that decomposing the domain of `root` may visit, starting with `root`.
+/// Follows [`TaskRef`]s, and the [`SmartObject`]s that a [`SmartObjectSlot`] may decompose into.
+pub(crate) fn domain_tasks(world: &World, root: Entity) -> Vec<Entity> {
+    let mut tasks = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![root];
+    let mut smart_objects = false;
+    while let Some(task) = stack.pop() {
+        let Ok(entity) = world.get_entity(task) else {
+            continue;
+        };
+        if !visited.insert(task) {
+            continue;
+        }
+        tasks.push(task);
+        if let Some(&TaskRef(target)) = entity.get::<TaskRef>() {
+            stack.push(target);
+        }
+        if entity.contains::<SmartObjectSlot>() && !smart_objects {
+            smart_objects = true;
+            if let Some(mut objects) = world.try_query_filtered::<Entity, With<SmartObject>>() {
+                stack.extend(objects.iter(world));
+            }
+        }
+        stack.extend(entity.get::<Tasks>().into_iter().flatten());
+    }
+    tasks
+}
+
 /// Used to allow calling [`CompoundAppExt::add_compound_task`] on [`App`].
 pub trait CompoundAppExt {
     /// Registers a new [`CompoundTask`] with the [`App`].
@@ -75,20 +462,177 @@ pub trait CompoundAppExt {
 
 impl CompoundAppExt for App {
     fn add_compound_task<C: CompoundTask>(&mut self) -> &mut Self {
-        self.add_observer(insert_type_erased_task::<C>)
+        self.init_resource::<DecomposeSystems>()
+            .init_resource::<DecomposeStack>()
+            .add_observer(insert_type_erased_task::<C>)
             .add_observer(remove_type_erased_task::<C>);
         self
     }
 }
 
-fn insert_type_erased_task<C: CompoundTask>(insert: On<Insert, C>, mut commands: Commands) {
-    let system_id = C::register_decompose(&mut commands);
+fn insert_type_erased_task<C: CompoundTask>(
+    add: On<Add, C>,
+    mut systems: ResMut<DecomposeSystems>,
+    mut commands: Commands,
+) {
+    let pool = systems
+        .entry(TypeId::of::<C>())
+        .or_insert_with(|| DecomposeSystemPool {
+            ids: Vec::new(),
+            running: 0,
+            users: 0,
+            register: C::register_decompose,
+        });
+    if pool.ids.is_empty() {
+        pool.ids.push(C::register_decompose(&mut commands));
+    }
+    pool.users += 1;
     commands
-        .entity(insert.entity)
-        .try_insert(TypeErasedCompoundTask::new(system_id));
+        .entity(add.entity)
+        .try_insert(TypeErasedCompoundTask::new::<C>(pool.ids[0]));
 }
-fn remove_type_erased_task<C: CompoundTask>(remove: On<Remove, C>, mut commands: Commands) {
+
+fn remove_type_erased_task<C: CompoundTask>(
+    remove: On<Remove, C>,
+    mut systems: ResMut<DecomposeSystems>,
+    mut commands: Commands,
+) {
     commands
         .entity(remove.entity)
         .try_remove::<TypeErasedCompoundTask>();
+    let Some(pool) = systems.get_mut(&TypeId::of::<C>()) else {
+        return;
+    };
+    pool.users = pool.users.saturating_sub(1);
+    // Otherwise, the pool is cleaned up once the running systems are done
+    if pool.users == 0 && pool.running == 0 {
+        for id in pool.ids.drain(..) {
+            commands.unregister_system(id);
+        }
+        systems.remove(&TypeId::of::<C>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::MinimalPlugins;
+
+    use crate::BaePlugin;
+
+    use super::*;
+
+    #[test]
+    fn clamps_recursion_depth() {
+        let mut world = World::new();
+        let planner = world.spawn(MaxRecursionDepth(usize::MAX)).id();
+        assert_eq!(
+            MaxRecursionDepth::of(&world, planner),
+            MaxRecursionDepth::MAX
+        );
+    }
+
+    #[test]
+    fn shares_decompose_system() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, BaePlugin::default()));
+        let selects: Vec<_> = (0..10)
+            .map(|_| app.world_mut().spawn(Select).id())
+            .collect();
+        app.update();
+
+        let ids: Vec<_> = selects
+            .iter()
+            .map(|&entity| {
+                app.world()
+                    .get::<TypeErasedCompoundTask>(entity)
+                    .unwrap()
+                    .decompose
+            })
+            .collect();
+        assert!(ids.iter().all(|&id| id == ids[0]));
+        let systems = app.world().resource::<DecomposeSystems>();
+        assert_eq!(systems[&TypeId::of::<Select>()].ids.len(), 1);
+        assert_eq!(systems[&TypeId::of::<Select>()].users, 10);
+
+        for entity in selects {
+            app.world_mut().despawn(entity);
+        }
+        app.update();
+        let systems = app.world().resource::<DecomposeSystems>();
+        assert!(!systems.contains_key(&TypeId::of::<Select>()));
+        assert!(app.world().get_entity(ids[0].entity()).is_err());
+    }
+
+    #[test]
+    fn releases_decompose_systems_removed_while_running() {
+        #[derive(Component)]
+        struct Despawning;
+
+        impl CompoundTask for Despawning {
+            fn register_decompose(commands: &mut Commands) -> DecomposeId {
+                commands.register_system(|In(ctx): In<DecomposeInput>, world: &mut World| {
+                    world.despawn(ctx.compound_task);
+                    DecomposeResult::Failure
+                })
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, BaePlugin::default()))
+            .add_compound_task::<Despawning>();
+        let agent = app
+            .world_mut()
+            .spawn((Plan::new(), Select, tasks![Despawning]))
+            .id();
+        app.world_mut().flush();
+        let id = app
+            .world()
+            .resource::<DecomposeSystems>()
+            .get(&TypeId::of::<Despawning>())
+            .unwrap()
+            .ids[0];
+
+        app.world_mut().trigger(UpdatePlan::new(agent));
+        app.world_mut().flush();
+        let systems = app.world().resource::<DecomposeSystems>();
+        assert!(!systems.contains_key(&TypeId::of::<Despawning>()));
+        assert!(app.world().get_entity(id.entity()).is_err());
+    }
+
+    #[test]
+    fn world_state_copies_on_write() {
+        let mut state = WorldState::new(Props::default());
+        state.set("a", Value::from(true));
+        let mut branch = state.clone();
+        assert!(Arc::ptr_eq(&state.0, &branch.0));
+
+        branch.set("a", Value::from(false));
+        assert!(!Arc::ptr_eq(&state.0, &branch.0));
+        drop(branch);
+        assert_eq!(*state.entry("a".into()).or_default(), Value::from(true));
+    }
+
+    #[test]
+    fn conditions_with_mutable_props_keep_working() {
+        let state = WorldState::new(Props::default());
+        let condition = Condition::new(|props: &mut Props| {
+            props.set("touched", true);
+            true
+        });
+        assert!(condition.is_fullfilled(&state));
+        assert!(state.iter().next().is_none());
+    }
+
+    #[test]
+    fn evaluating_conditions_does_not_copy_world_state() {
+        let mut state = WorldState::new(Props::default());
+        state.set("a", Value::from(true));
+        let branch = state.clone();
+
+        assert!(Condition::eq("a", true).is_fullfilled(&branch));
+        assert!(Condition::eq("missing", false).is_fullfilled(&branch));
+        assert!(Condition::in_range("missing", -1.0..1.0).is_fullfilled(&branch));
+        assert!(Arc::ptr_eq(&state.0, &branch.0));
+        assert_eq!(branch.len(), 1);
+    }
 }
@@ -0,0 +1,57 @@
+//! Contains the [`TaskRef`] [`CompoundTask`] for reusing a subtree in multiple places of a domain.
+
+use crate::{
+    diagnostics::count_visited_task,
+    prelude::*,
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, decompose_subtask},
+};
+
+/// A [`CompoundTask`] that decomposes the task it points at, as if that task was in place of the reference.
+/// This lets multiple domains, or multiple places of the same domain, share a subtree without copying it.
+///
+/// The [`Condition`]s and [`Effect`]s of the reference itself are checked and applied on top of the ones of the referenced task.
+/// The [`Mtr`](crate::plan::mtr::Mtr) is the one of the referenced task, so the reference adds no index of its own.
+///
+/// Referenced tasks may contain references themselves, even to a task further up, which makes the domain recursive.
+/// Recursive decompositions fail once they exceed the [`MaxRecursionDepth`] of the agent.
+///
+/// Changing a referenced subtree also drops the entries of all referencing domains from a [`PlanCache`](crate::plan::cache::PlanCache).
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn spawn_agents(mut commands: Commands) {
+///     let flee = commands
+///         .spawn((
+///             Select,
+///             tasks![Operator::new(|_: In<OperatorInput>| OperatorStatus::Success)],
+///         ))
+///         .id();
+///     commands.spawn((
+///         Select,
+///         tasks![(TaskRef(flee), conditions![Condition::eq("hurt", true)]), Operator::noop()],
+///     ));
+///     commands.spawn((Select, tasks![TaskRef(flee)]));
+/// }
+/// ```
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct TaskRef(pub Entity);
+
+impl CompoundTask for TaskRef {
+    fn register_decompose(commands: &mut Commands) -> DecomposeId {
+        commands.register_system(decompose_task_ref)
+    }
+}
+
+fn decompose_task_ref(In(ctx): In<DecomposeInput>, world: &mut World) -> DecomposeResult {
+    let Some(&TaskRef(task)) = world.get::<TaskRef>(ctx.compound_task) else {
+        return DecomposeResult::Failure;
+    };
+    if world.get_entity(task).is_err() {
+        warn!(planner=?ctx.planner, reference=?ctx.compound_task, ?task, "referenced task does not exist");
+        return DecomposeResult::Failure;
+    }
+    count_visited_task(world);
+    decompose_subtask(world, task, ctx)
+}
@@ -0,0 +1,126 @@
+//! Contains the [`Repeat`] [`CompoundTask`]
+
+use core::mem;
+
+use crate::{
+    diagnostics::count_visited_task,
+    prelude::*,
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, TaskSource, decompose_subtask},
+};
+
+/// A [`CompoundTask`] that decomposes all subtasks like a [`Sequence`], and then does so again, as often as configured.
+/// Each repetition sees the [`Props`] as modified by the [`Effect`]s of the previous ones.
+/// If no repetition takes place, the decomposition fails.
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn spawn_agent(mut commands: Commands) {
+///     commands.spawn((
+///         Repeat::While(Condition::lt("items", 3)),
+///         tasks![(
+///             Name::new("take next item"),
+///             Operator::new(|_: In<OperatorInput>| OperatorStatus::Success),
+///             effects![Effect::inc::<i32>("items", 1)],
+///         )],
+///     ));
+/// }
+/// ```
+#[derive(Debug, Component, Clone, PartialEq, Reflect)]
+#[reflect(Component)]
+pub enum Repeat {
+    /// Repeat the subtasks this many times.
+    Times(u16),
+    /// Repeat the subtasks as long as the condition is fulfilled before a repetition.
+    /// Loops that are still going after [`MaxRepetitions`] repetitions fail.
+    While(Condition),
+}
+
+/// How often a [`Repeat::While`] may loop before it fails. Insert this next to the [`Repeat`],
+/// otherwise the default of 32 is used.
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct MaxRepetitions(pub usize);
+
+impl Default for MaxRepetitions {
+    fn default() -> Self {
+        Self(32)
+    }
+}
+
+impl CompoundTask for Repeat {
+    fn register_decompose(commands: &mut Commands) -> DecomposeId {
+        commands.register_system(decompose_repeat)
+    }
+}
+
+fn decompose_repeat(In(mut ctx): In<DecomposeInput>, world: &mut World) -> DecomposeResult {
+    let (Some(repeat), Some(tasks)) = (
+        world.get::<Repeat>(ctx.compound_task).cloned(),
+        world
+            .get::<Tasks>(ctx.compound_task)
+            .map(|tasks| tasks.to_vec()),
+    ) else {
+        return DecomposeResult::Failure;
+    };
+    let max_repetitions = world
+        .get::<MaxRepetitions>(ctx.compound_task)
+        .copied()
+        .unwrap_or_default()
+        .0;
+    let mut plan = Plan::new();
+    let mut repetitions = 0;
+    loop {
+        match &repeat {
+            Repeat::Times(times) if repetitions >= usize::from(*times) => break,
+            Repeat::Times(_) => {}
+            Repeat::While(condition) => {
+                if !world.condition_holds(condition, &ctx.world_state, ctx.planner) {
+                    break;
+                }
+                if repetitions >= max_repetitions {
+                    warn!(
+                        planner = ?ctx.planner,
+                        task = ?ctx.compound_task,
+                        "repeated more than {max_repetitions} times without the loop condition failing, \
+                        see `MaxRepetitions`"
+                    );
+                    return DecomposeResult::Failure;
+                }
+            }
+        }
+        for &task in &tasks {
+            count_visited_task(world);
+            let result = decompose_subtask(
+                world,
+                task,
+                DecomposeInput {
+                    planner: ctx.planner,
+                    compound_task: ctx.compound_task,
+                    world_state: ctx.world_state,
+                    previous_mtr: ctx.previous_mtr.clone(),
+                    // Only the first subtask needs to inherit our conditions
+                    conditions: mem::take(&mut ctx.conditions),
+                },
+            );
+            match result {
+                DecomposeResult::Success {
+                    sub_plan,
+                    world_state,
+                } => {
+                    plan.merge(sub_plan);
+                    ctx.world_state = world_state;
+                }
+                result => return result,
+            }
+        }
+        repetitions += 1;
+    }
+    if plan.is_empty() {
+        return DecomposeResult::Failure;
+    }
+    DecomposeResult::Success {
+        sub_plan: plan,
+        world_state: ctx.world_state,
+    }
+}
@@ -1,9 +1,9 @@
 //! Contains the [`Select`] [`CompoundTask`]
 
 use crate::{
-    plan::TaskNode,
+    plan::mtr::Mtr,
     prelude::*,
-    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, TypeErasedCompoundTask},
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, TaskSource, decompose_subtask},
 };
 
 /// A [`CompoundTask`] that decomposes into the first valid subtask.
@@ -17,119 +17,53 @@ impl CompoundTask for Select {
     }
 }
 
-fn decompose_select(
-    In(mut ctx): In<DecomposeInput>,
-    world: &mut World,
-    mut task_relations: Local<QueryState<&Tasks>>,
-    mut individual_tasks: Local<
-        QueryState<
-            (
-                Entity,
-                Has<Operator>,
-                Option<&TypeErasedCompoundTask>,
-                Option<&Conditions>,
-                Option<&Effects>,
-            ),
-            Or<(With<Operator>, With<TypeErasedCompoundTask>)>,
-        >,
-    >,
-    mut conditions: Local<QueryState<(Entity, &Condition)>>,
-    mut effects: Local<QueryState<(Entity, &Effect)>>,
-    mut individual_tasks_scratch: Local<
-        Vec<(
-            Entity,
-            bool,
-            Option<TypeErasedCompoundTask>,
-            Option<Conditions>,
-            Option<Effects>,
-        )>,
-    >,
-) -> DecomposeResult {
-    let mut plan = Plan::new();
-
-    let Ok(tasks) = task_relations.get(world, ctx.compound_task) else {
-        return DecomposeResult::Failure;
-    };
-    individual_tasks_scratch.extend(individual_tasks.iter_many(world, tasks).map(
-        |(task_entity, has_operator, compound_task, condition_relations, effect_relations)| {
-            (
-                task_entity,
-                has_operator,
-                compound_task.cloned(),
-                condition_relations.cloned(),
-                effect_relations.cloned(),
-            )
-        },
-    ));
+fn decompose_select(In(ctx): In<DecomposeInput>, world: &mut World) -> DecomposeResult {
+    let tasks = world.subtasks(ctx.compound_task);
+    select(world, &tasks, ctx)
+}
 
-    'task: for (
-        i,
-        (task_entity, has_operator, compound_task, condition_relations, effect_relations),
-    ) in individual_tasks_scratch.drain(..).enumerate()
-    {
-        let mtr = plan.mtr.clone().with(i as u16);
+/// Decomposes the first of `tasks` that can be decomposed. The [`Mtr`](crate::plan::mtr::Mtr) is the index of that task.
+pub(crate) fn select(
+    source: &mut impl TaskSource,
+    tasks: &[Entity],
+    ctx: DecomposeInput,
+) -> DecomposeResult {
+    for (i, &task) in tasks.iter().enumerate() {
+        source.count_visited_task();
+        let mtr = Mtr::none().with(i as u16);
         if mtr > ctx.previous_mtr {
             return DecomposeResult::Rejection;
         }
-        if let Some(condition_relations) = condition_relations {
-            for (entity, condition) in conditions.iter_many(world, condition_relations.iter()) {
-                if !condition.is_fullfilled(&mut ctx.world_state) {
-                    continue 'task;
-                }
-                ctx.conditions.push(entity);
-            }
+        if source.is_reserved_by_other(task, ctx.planner) {
+            continue;
         }
-        if has_operator {
-            let index = plan.add_node(TaskNode {
-                entity: task_entity,
-                composite: false,
-                effects: vec![],
+        // Each alternative starts from our state, so a failed one leaves nothing behind
+        let result = decompose_subtask(
+            source,
+            task,
+            DecomposeInput {
+                planner: ctx.planner,
+                compound_task: ctx.compound_task,
+                world_state: ctx.world_state.clone(),
+                previous_mtr: ctx.previous_mtr.clone(),
                 conditions: ctx.conditions.clone(),
-            });
-
-            plan.push_back(index);
-        } else if let Some(compound_task) = compound_task {
-            let result = world.run_system_with(
-                compound_task.decompose,
-                DecomposeInput {
-                    planner: ctx.planner,
-                    compound_task: task_entity,
-                    world_state: ctx.world_state.clone(),
-                    previous_mtr: ctx.previous_mtr.clone(),
-                    conditions: ctx.conditions.clone(),
-                },
-            );
-            world.flush();
-            match result {
-                Ok(DecomposeResult::Success {
+            },
+        );
+        match result {
+            DecomposeResult::Success {
+                mut sub_plan,
+                world_state,
+            } => {
+                // only use the first match
+                sub_plan.mtr = mtr;
+                return DecomposeResult::Success {
                     sub_plan,
                     world_state,
-                }) => {
-                    plan.merge(sub_plan);
-                    ctx.world_state = world_state;
-                }
-                Ok(DecomposeResult::Rejection) => return DecomposeResult::Rejection,
-                Ok(DecomposeResult::Failure) | Err(_) => continue,
-            }
-        } else {
-            unreachable!()
-        }
-        if plan.is_empty() {
-            return DecomposeResult::Failure;
-        }
-        if let Some(effect_relations) = effect_relations {
-            for (entity, effect) in effects.iter_many(world, effect_relations.iter()) {
-                effect.apply(&mut ctx.world_state);
-                let idx = *plan.back().unwrap();
-                plan.nodes[idx].effects.push(entity);
+                };
             }
+            DecomposeResult::Rejection => return DecomposeResult::Rejection,
+            DecomposeResult::Failure => continue,
         }
-        // only use the first match
-        plan.mtr.push(i as u16);
-        return DecomposeResult::Success {
-            sub_plan: plan,
-            world_state: ctx.world_state,
-        };
     }
     DecomposeResult::Failure
 }
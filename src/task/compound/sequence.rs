@@ -1,9 +1,10 @@
 //! Contains the [`Sequence`] [`CompoundTask`]
 
+use core::mem;
+
 use crate::{
-    plan::TaskNode,
     prelude::*,
-    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, TypeErasedCompoundTask},
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, TaskSource, decompose_subtask},
 };
 
 /// A [`CompoundTask`] that decomposes into all subtasks, given that they are all valid.
@@ -17,124 +18,52 @@ impl CompoundTask for Sequence {
     }
 }
 
-fn decompose_sequence(
-    In(mut ctx): In<DecomposeInput>,
-    world: &mut World,
-    mut task_relations: Local<QueryState<&Tasks>>,
-    mut individual_tasks: Local<
-        QueryState<
-            (
-                Entity,
-                Has<Operator>,
-                Option<&TypeErasedCompoundTask>,
-                Option<&Conditions>,
-                Option<&Effects>,
-            ),
-            Or<(With<Operator>, With<TypeErasedCompoundTask>)>,
-        >,
-    >,
-    mut conditions: Local<QueryState<(Entity, &Condition)>>,
-    mut effects: Local<QueryState<(Entity, &Effect)>>,
-    mut individual_tasks_scratch: Local<
-        Vec<(
-            Entity,
-            bool,
-            Option<TypeErasedCompoundTask>,
-            Option<Conditions>,
-            Option<Effects>,
-        )>,
-    >,
+fn decompose_sequence(In(ctx): In<DecomposeInput>, world: &mut World) -> DecomposeResult {
+    let tasks = world.subtasks(ctx.compound_task);
+    sequence(world, &tasks, ctx)
+}
+
+/// Decomposes all of `tasks` in order, failing if any of them fails.
+pub(crate) fn sequence(
+    source: &mut impl TaskSource,
+    tasks: &[Entity],
+    mut ctx: DecomposeInput,
 ) -> DecomposeResult {
     let mut plan = Plan::new();
-
-    let Ok(tasks) = task_relations.get(world, ctx.compound_task) else {
-        return DecomposeResult::Failure;
-    };
-    individual_tasks_scratch.extend(individual_tasks.iter_many(world, tasks).map(
-        |(task_entity, has_operator, compound_task, condition_relations, effect_relations)| {
-            (
-                task_entity,
-                has_operator,
-                compound_task.cloned(),
-                condition_relations.cloned(),
-                effect_relations.cloned(),
-            )
-        },
-    ));
-    let mut found_anything = false;
-    for (task_entity, has_operator, compound_task, condition_relations, effect_relations) in
-        individual_tasks_scratch.drain(..)
-    {
-        let mut individual_conditions = Vec::new();
-        if let Some(condition_relations) = condition_relations {
-            for (entity, condition) in conditions.iter_many(world, condition_relations.iter()) {
-                if !condition.is_fullfilled(&mut ctx.world_state) {
-                    return DecomposeResult::Failure;
-                }
-                individual_conditions.push(entity);
-            }
-        }
-        let conditions = if !found_anything {
-            // Only the first "entry" subtask needs to inherit our conditions
-            ctx.conditions.extend(individual_conditions);
-            ctx.conditions.clone()
-        } else {
-            individual_conditions
-        };
-        if has_operator {
-            let index = plan.add_node(TaskNode {
-                entity: task_entity,
-                composite: false,
-                effects: vec![],
-                conditions,
-            });
-
-            plan.push_back(index);
-        } else if let Some(compound_task) = compound_task {
-            let result = world.run_system_with(
-                compound_task.decompose,
-                DecomposeInput {
-                    planner: ctx.planner,
-                    compound_task: task_entity,
-                    world_state: ctx.world_state.clone(),
-                    previous_mtr: ctx.previous_mtr.clone(),
-                    conditions,
+    for (i, &task) in tasks.iter().enumerate() {
+        source.count_visited_task();
+        let result = decompose_subtask(
+            source,
+            task,
+            DecomposeInput {
+                planner: ctx.planner,
+                compound_task: ctx.compound_task,
+                world_state: mem::take(&mut ctx.world_state),
+                previous_mtr: ctx.previous_mtr.clone(),
+                // Only the first "entry" subtask needs to inherit our conditions
+                conditions: if i == 0 {
+                    mem::take(&mut ctx.conditions)
+                } else {
+                    Vec::new()
                 },
-            );
-            world.flush();
-            match result {
-                Ok(DecomposeResult::Success {
-                    sub_plan,
-                    world_state,
-                }) => {
-                    plan.merge(sub_plan);
-                    ctx.world_state = world_state;
-                }
-                Ok(DecomposeResult::Rejection) => return DecomposeResult::Rejection,
-                Ok(DecomposeResult::Failure) | Err(_) => return DecomposeResult::Failure,
+            },
+        );
+        match result {
+            DecomposeResult::Success {
+                sub_plan,
+                world_state,
+            } => {
+                plan.merge(sub_plan);
+                ctx.world_state = world_state;
             }
-        } else {
-            unreachable!()
+            result => return result,
         }
-        if plan.is_empty() {
-            return DecomposeResult::Failure;
-        }
-        if let Some(effect_relations) = effect_relations {
-            for (entity, effect) in effects.iter_many(world, effect_relations.iter()) {
-                effect.apply(&mut ctx.world_state);
-                let idx = *plan.back().unwrap();
-                plan.nodes[idx].effects.push(entity);
-            }
-        }
-        found_anything = true;
     }
-
-    if found_anything {
-        DecomposeResult::Success {
-            sub_plan: plan,
-            world_state: ctx.world_state,
-        }
-    } else {
-        DecomposeResult::Failure
+    if plan.is_empty() {
+        return DecomposeResult::Failure;
+    }
+    DecomposeResult::Success {
+        sub_plan: plan,
+        world_state: ctx.world_state,
     }
 }
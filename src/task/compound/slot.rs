@@ -0,0 +1,166 @@
+//! Contains the [`Slot`] [`CompoundTask`] for swapping subtrees of a domain at runtime.
+
+use bevy_platform::collections::HashMap;
+
+use crate::{
+    diagnostics::count_visited_task,
+    plan::{execution::end_plan, history::PlanEndReason},
+    prelude::*,
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, decompose_subtask},
+};
+
+/// A [`CompoundTask`] that decomposes into whatever task is currently plugged into it, e.g. the combat subtree
+/// of the weapon an agent has equipped. Without a plugged task, it fails to decompose.
+/// The plugged task decomposes as if it was in place of the slot, including its [`Mtr`](crate::plan::mtr::Mtr).
+///
+/// Use [`SlotCommandsExt::plug`] and [`SlotCommandsExt::unplug`] to change the plugged task.
+/// The plugged task is the only entry of the slot's [`Tasks`].
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Slot;
+
+impl CompoundTask for Slot {
+    fn register_decompose(commands: &mut Commands) -> DecomposeId {
+        commands.register_system(decompose_slot)
+    }
+}
+
+fn decompose_slot(In(ctx): In<DecomposeInput>, world: &mut World) -> DecomposeResult {
+    let Some(task) = world
+        .get::<Tasks>(ctx.compound_task)
+        .and_then(|tasks| tasks.first().copied())
+    else {
+        return DecomposeResult::Failure;
+    };
+    count_visited_task(world);
+    decompose_subtask(world, task, ctx)
+}
+
+/// The [`Plan`]s containing each [`Slot`], so plugging a slot only checks the plans that went through it.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct SlotUsers {
+    planners: HashMap<Entity, Vec<Entity>>,
+    slots: HashMap<Entity, Vec<Entity>>,
+}
+
+impl SlotUsers {
+    fn planners(&self, slot: Entity) -> &[Entity] {
+        self.planners.get(&slot).map_or(&[], Vec::as_slice)
+    }
+
+    fn insert(&mut self, planner: Entity, slots: Vec<Entity>) {
+        for &slot in &slots {
+            self.planners.entry(slot).or_default().push(planner);
+        }
+        if !slots.is_empty() {
+            self.slots.insert(planner, slots);
+        }
+    }
+
+    fn remove(&mut self, planner: Entity) {
+        for slot in self.slots.remove(&planner).into_iter().flatten() {
+            if let Some(planners) = self.planners.get_mut(&slot) {
+                planners.retain(|&other| other != planner);
+                if planners.is_empty() {
+                    self.planners.remove(&slot);
+                }
+            }
+        }
+    }
+}
+
+/// Indexes the [`Slot`]s of a plan when it is inserted.
+pub(crate) fn track_slot_users(
+    insert: On<Insert, Plan>,
+    plans: Query<&Plan>,
+    slots: Query<(), With<Slot>>,
+    mut users: ResMut<SlotUsers>,
+) {
+    let Ok(plan) = plans.get(insert.entity) else {
+        return;
+    };
+    let mut used: Vec<_> = plan
+        .nodes
+        .iter()
+        .map(|node| node.entity)
+        .filter(|&task| slots.contains(task))
+        .collect();
+    used.sort();
+    used.dedup();
+    users.insert(insert.entity, used);
+}
+
+/// Drops the [`Slot`]s of a plan that ended or is being replaced from the index.
+pub(crate) fn release_slot_users(replace: On<Replace, Plan>, mut users: ResMut<SlotUsers>) {
+    users.remove(replace.entity);
+}
+
+/// Used to allow calling [`SlotCommandsExt::plug`] and [`SlotCommandsExt::unplug`] on the [`EntityCommands`] of a [`Slot`].
+pub trait SlotCommandsExt {
+    /// Plugs `task` into this [`Slot`], unplugging the previous task, and triggers [`UpdatePlan`] on the domain root.
+    fn plug(&mut self, task: Entity) -> &mut Self;
+
+    /// Unplugs the task from this [`Slot`] without despawning it.
+    /// If the running plan of the domain root uses the task, that plan is aborted, and the agent replans.
+    fn unplug(&mut self) -> &mut Self;
+}
+
+impl SlotCommandsExt for EntityCommands<'_> {
+    fn plug(&mut self, task: Entity) -> &mut Self {
+        self.queue(move |mut slot: EntityWorldMut| {
+            let id = slot.id();
+            slot.world_scope(|world| plug_slot(world, id, Some(task)));
+        })
+    }
+
+    fn unplug(&mut self) -> &mut Self {
+        self.queue(|mut slot: EntityWorldMut| {
+            let id = slot.id();
+            slot.world_scope(|world| plug_slot(world, id, None));
+        })
+    }
+}
+
+fn plug_slot(world: &mut World, slot: Entity, task: Option<Entity>) {
+    let mut root = slot;
+    while let Some(task_of) = world.get::<TaskOf>(root) {
+        root = task_of.0;
+    }
+    let plugged = world
+        .get::<Tasks>(slot)
+        .map(|tasks| tasks.to_vec())
+        .unwrap_or_default();
+    // The slot may be reached through a `TaskRef` from any domain, so all plans that went through it are checked
+    let users = world
+        .get_resource::<SlotUsers>()
+        .map(|users| users.planners(slot).to_vec())
+        .unwrap_or_default();
+    for old in plugged.into_iter().filter(|&old| Some(old) != task) {
+        let affected: Vec<_> = users
+            .iter()
+            .filter_map(|&planner| {
+                let plan = world.get::<Plan>(planner)?;
+                let front = *plan.front()?;
+                let old_nodes: Vec<_> = (0..plan.nodes.len())
+                    .filter(|&node| plan.nodes[node].entity == old)
+                    .collect();
+                plan.iter()
+                    .any(|&step| old_nodes.iter().any(|&node| plan.is_within(step, node)))
+                    .then_some((planner, plan.nodes[front].entity))
+            })
+            .collect();
+        for (planner, operator) in affected {
+            debug!(?planner, ?slot, task=?old, "unplugged task is running, aborting plan");
+            end_plan(world, planner, PlanEndReason::OperatorFailed { operator });
+        }
+        world.entity_mut(old).remove::<TaskOf>();
+    }
+    if let Some(task) = task
+        && let Ok(mut task) = world.get_entity_mut(task)
+    {
+        task.insert(TaskOf(slot));
+        if world.get::<Plan>(root).is_some() {
+            world.trigger(UpdatePlan::new(root));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Contains [`SmartObject`]s and the [`SmartObjectSlot`] [`CompoundTask`] that lets agents use them.
+
+use alloc::sync::Arc;
+use core::fmt::{self, Debug};
+
+use crate::{
+    plan::target::actor,
+    prelude::*,
+    task::compound::{DecomposeId, DecomposeInput, DecomposeResult, select::select},
+};
+
+/// A world object, like a bench or an ammo crate, that advertises the task subtrees in its [`Tasks`] to nearby agents.
+/// It decomposes like a [`Select`], so the first valid subtree is used.
+///
+/// Agents only consider smart objects that are eligible for them, and only if their domain contains a [`SmartObjectSlot`].
+/// The [`Condition`]s and [`Effect`]s of the smart object itself apply to all of its subtrees.
+/// [`OperatorInput::smart_object`] tells operators which smart object they belong to.
+/// ```
+/// use bevy::prelude::*;
+/// use bevy_bae::prelude::*;
+///
+/// fn spawn_bench(mut commands: Commands) {
+///     commands.spawn((
+///         Name::new("bench"),
+///         Transform::default(),
+///         SmartObject::with_eligibility(|agent, bench| {
+///             let (Some(agent), Some(bench)) = (agent.get::<Transform>(), bench.get::<Transform>()) else {
+///                 return false;
+///             };
+///             agent.translation.distance(bench.translation) < 10.0
+///         }),
+///         tasks![(
+///             Name::new("sit"),
+///             Operator::new(|_: In<OperatorInput>| OperatorStatus::Success),
+///         )],
+///     ));
+/// }
+/// ```
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+#[require(Select)]
+pub struct SmartObject {
+    #[reflect(ignore, default = "eligible_for_all")]
+    eligible: Arc<dyn Fn(EntityRef, EntityRef) -> bool + Send + Sync + 'static>,
+}
+
+impl Default for SmartObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmartObject {
+    /// Creates a smart object that is eligible for all agents.
+    pub fn new() -> Self {
+        Self {
+            eligible: eligible_for_all(),
+        }
+    }
+
+    /// Creates a smart object that is eligible for the agents for which `eligible` returns `true`.
+    /// The first argument is the agent, see [`OperatorInput::actor`], and the second one is the smart object.
+    pub fn with_eligibility(
+        eligible: impl Fn(EntityRef, EntityRef) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            eligible: Arc::new(eligible),
+        }
+    }
+
+    /// Returns whether this smart object may be used by `agent`.
+    pub fn is_eligible(&self, agent: EntityRef, object: EntityRef) -> bool {
+        (self.eligible)(agent, object)
+    }
+}
+
+fn eligible_for_all() -> Arc<dyn Fn(EntityRef, EntityRef) -> bool + Send + Sync + 'static> {
+    Arc::new(|_, _| true)
+}
+
+impl Debug for SmartObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmartObject")
+            .field("eligible", &"<callback>")
+            .finish()
+    }
+}
+
+/// A [`CompoundTask`] that decomposes into the first eligible [`SmartObject`] that can be decomposed.
+/// Smart objects are tried in the order of their entities.
+///
+/// Decompositions using this depend on more than [`Props`], so a [`PlanCache`](crate::plan::cache::PlanCache) never caches them.
+#[derive(Debug, Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct SmartObjectSlot;
+
+impl CompoundTask for SmartObjectSlot {
+    fn register_decompose(commands: &mut Commands) -> DecomposeId {
+        commands.register_system(decompose_smart_object_slot)
+    }
+}
+
+fn decompose_smart_object_slot(
+    In(ctx): In<DecomposeInput>,
+    world: &mut World,
+    mut objects: Local<QueryState<(Entity, &SmartObject)>>,
+) -> DecomposeResult {
+    let Ok(agent) = world.get_entity(actor(world, ctx.planner)) else {
+        return DecomposeResult::Failure;
+    };
+    let mut candidates: Vec<_> = objects
+        .iter(world)
+        .filter(|&(object, smart_object)| smart_object.is_eligible(agent, world.entity(object)))
+        .map(|(object, _)| object)
+        .collect();
+    candidates.sort();
+    select(world, &candidates, ctx)
+}
+
+/// Returns the [`SmartObject`] that `task` was contributed by, if any.
+pub(crate) fn smart_object_of(world: &World, task: Entity) -> Option<Entity> {
+    let mut current = world.get::<TaskOf>(task).map(|task_of| task_of.0);
+    while let Some(entity) = current {
+        if world.get::<SmartObject>(entity).is_some() {
+            return Some(entity);
+        }
+        current = world.get::<TaskOf>(entity).map(|task_of| task_of.0);
+    }
+    None
+}
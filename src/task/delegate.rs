@@ -0,0 +1,225 @@
+//! Contains the [`Delegate`] [`Operator`] for planners that hand sub-goals to other agents, e.g. squads to their members.
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use bevy_bae::prelude::*;
+//!
+//! fn spawn_squad(mut commands: Commands) {
+//!     let member = commands
+//!         .spawn((
+//!             Select,
+//!             tasks![(
+//!                 Operator::new(|_: In<OperatorInput>| OperatorStatus::Success),
+//!                 conditions![Condition::eq("flank", true)],
+//!             )],
+//!         ))
+//!         .id();
+//!     commands.spawn((
+//!         Select,
+//!         tasks![
+//!             (
+//!                 Name::new("order flank"),
+//!                 Delegate::new(member)
+//!                     .with_prop("flank", true)
+//!                     .with_failure_prop("flank_failed"),
+//!                 conditions![Condition::eq("flank_failed", false)],
+//!             ),
+//!             Operator::noop(),
+//!         ],
+//!     ));
+//! }
+//! ```
+
+use bevy_mod_props::PropsMutExt as _;
+
+use crate::{plan::target::PlanTarget, prelude::*};
+
+/// An [`Operator`] that hands a sub-goal to another agent and waits for it.
+///
+/// When it starts, it sets [`Delegate::props`] on the member, clears the member's [`Plan`] and triggers [`UpdatePlan`] on it.
+/// It returns [`OperatorStatus::Ongoing`] until the member's next plan ends, and then [`OperatorStatus::Success`]
+/// if that plan completed or [`OperatorStatus::Failure`] if it failed or no plan could be found.
+///
+/// To react to failures of the member, set [`Delegate::failure_prop`]. This prop of the delegating agent is set to `false`
+/// when the delegation starts and to `true` when the member failed, so that [`Condition`]s can check it.
+#[derive(Component, Clone, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+#[require(Operator = Operator::shared(run_delegate))]
+pub struct Delegate {
+    /// The agent that receives the sub-goal.
+    pub member: Entity,
+    /// The domain root the member plans with instead of its own. If set, this entity becomes the planner of the member
+    /// through a [`PlanTarget`], and its [`Plan`] is the one that is waited for.
+    /// The [`Plan`] and [`PlanTarget`] are removed again once the delegation ends or the delegating plan is aborted.
+    pub task: Option<Entity>,
+    /// The props set on the member when the delegation starts.
+    #[reflect(ignore)]
+    pub props: Vec<(Ustr, Value)>,
+    /// The prop of the delegating agent that tells whether the member failed.
+    #[reflect(ignore)]
+    pub failure_prop: Option<Ustr>,
+}
+
+impl Delegate {
+    /// Creates a delegation to `member`, which plans with its own domain.
+    pub fn new(member: Entity) -> Self {
+        Self {
+            member,
+            task: None,
+            props: Vec::new(),
+            failure_prop: None,
+        }
+    }
+
+    /// Lets the member plan with the domain rooted at `task`. See [`Delegate::task`].
+    pub fn with_task(mut self, task: Entity) -> Self {
+        self.task = Some(task);
+        self
+    }
+
+    /// Sets the prop `name` on the member when the delegation starts.
+    pub fn with_prop(mut self, name: impl Into<Ustr>, value: impl Into<Value>) -> Self {
+        self.props.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets [`Delegate::failure_prop`].
+    pub fn with_failure_prop(mut self, name: impl Into<Ustr>) -> Self {
+        self.failure_prop = Some(name.into());
+        self
+    }
+
+    fn planner(&self) -> Entity {
+        self.task.unwrap_or(self.member)
+    }
+}
+
+/// Inserted on the planner of a member while a [`Delegate`] waits for it.
+#[derive(Component, Debug)]
+pub(crate) struct Delegation {
+    delegator: Entity,
+    operator: Entity,
+    succeeded: Option<bool>,
+    /// Whether the planner is a [`Delegate::task`] root, which only plans while delegated to.
+    task: bool,
+}
+
+impl Delegation {
+    /// Whether the planner is a [`Delegate::task`] root whose plan ended, so it must not replan.
+    pub(crate) fn is_finished_task(&self) -> bool {
+        self.task && self.succeeded.is_some()
+    }
+}
+
+/// Inserted on the delegating agent while it waits for the planner of a member.
+#[derive(Component, Debug)]
+pub(crate) struct Delegating(Entity);
+
+fn run_delegate(
+    In(input): In<OperatorInput>,
+    delegates: Query<&Delegate>,
+    delegations: Query<&Delegation>,
+    mut commands: Commands,
+) -> OperatorStatus {
+    let Ok(delegate) = delegates.get(input.operator) else {
+        return OperatorStatus::Failure;
+    };
+    let planner = delegate.planner();
+    match delegations.get(planner) {
+        Ok(delegation)
+            if delegation.delegator == input.entity && delegation.operator == input.operator =>
+        {
+            let Some(succeeded) = delegation.succeeded else {
+                return OperatorStatus::Ongoing;
+            };
+            commands.entity(input.entity).try_remove::<Delegating>();
+            end_task(&mut commands, planner, delegation);
+            if succeeded {
+                return OperatorStatus::Success;
+            }
+            if let Some(failure_prop) = delegate.failure_prop {
+                set_prop(&mut commands, input.props, failure_prop, true.into());
+            }
+            OperatorStatus::Failure
+        }
+        _ => {
+            debug!(delegator=?input.entity, member=?delegate.member, "delegating");
+            for &(name, value) in &delegate.props {
+                set_prop(&mut commands, delegate.member, name, value);
+            }
+            if let Some(failure_prop) = delegate.failure_prop {
+                set_prop(&mut commands, input.props, failure_prop, false.into());
+            }
+            if delegate.task.is_some() {
+                // The task root may have planned for another member before
+                commands
+                    .entity(planner)
+                    .try_insert(PlanTarget::new(delegate.member).with_props(delegate.member));
+            }
+            commands
+                .entity(input.entity)
+                .try_insert(Delegating(planner));
+            commands
+                .entity(planner)
+                .try_insert((
+                    Delegation {
+                        delegator: input.entity,
+                        operator: input.operator,
+                        succeeded: None,
+                        task: delegate.task.is_some(),
+                    },
+                    Plan::default(),
+                ))
+                .trigger(UpdatePlan::new);
+            OperatorStatus::Ongoing
+        }
+    }
+}
+
+fn set_prop(commands: &mut Commands, entity: Entity, name: Ustr, value: Value) {
+    commands.queue(move |world: &mut World| {
+        if let Ok(mut entity) = world.get_entity_mut(entity) {
+            entity.props_mut().set(name, value);
+        }
+    });
+}
+
+/// Reports to a waiting [`Delegate`] that the plan of `planner` ended.
+pub(crate) fn end_delegation(world: &mut World, planner: Entity, succeeded: bool) {
+    if let Some(mut delegation) = world.get_mut::<Delegation>(planner)
+        && delegation.succeeded.is_none()
+    {
+        delegation.succeeded = Some(succeeded);
+    }
+}
+
+/// Stops the planner of a member from waiting for its delegator.
+/// A [`Delegate::task`] root stops planning altogether.
+fn end_task(commands: &mut Commands, planner: Entity, delegation: &Delegation) {
+    if delegation.task {
+        commands
+            .entity(planner)
+            .try_remove::<(Plan, PlanTarget, Delegation)>();
+    } else {
+        commands.entity(planner).try_remove::<Delegation>();
+    }
+}
+
+/// Ends the delegation of an agent whose plan was replaced or aborted while it was waiting.
+pub(crate) fn cancel_delegation(
+    replace: On<Replace, Plan>,
+    delegating: Query<&Delegating>,
+    delegations: Query<&Delegation>,
+    mut commands: Commands,
+) {
+    let Ok(&Delegating(planner)) = delegating.get(replace.entity) else {
+        return;
+    };
+    commands.entity(replace.entity).try_remove::<Delegating>();
+    if let Ok(delegation) = delegations.get(planner)
+        && delegation.delegator == replace.entity
+    {
+        debug!(delegator=?replace.entity, ?planner, "cancelling delegation");
+        end_task(&mut commands, planner, delegation);
+    }
+}
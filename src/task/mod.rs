@@ -2,12 +2,15 @@
 
 use crate::prelude::*;
 
+pub mod batched;
 pub mod compound;
+pub mod delegate;
 pub mod operator;
 pub(crate) mod validation;
 
 /// The return type of [`Operator`]s.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+#[cfg_attr(feature = "record", derive(serde::Serialize, serde::Deserialize))]
 pub enum OperatorStatus {
     /// The task has completed successfully. Proceed to the next step of the plan.
     Success,
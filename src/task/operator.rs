@@ -1,17 +1,22 @@
 //! Contains [`Operator`] and associated types
 
-use core::fmt::Debug;
+use core::{any::TypeId, fmt::Debug, time::Duration};
 
 use bevy_ecs::system::SystemId;
 use bevy_ecs::{lifecycle::HookContext, world::DeferredWorld};
+use bevy_platform::collections::HashMap;
 
 use crate::prelude::*;
-use crate::task::validation::BaeTaskPresent;
+use crate::task::{batched::StartBatched, validation::BaeTaskPresent};
 
 /// The exact type of [`SystemId`] valid for [`Operator`]s.
 pub type OperatorId = SystemId<In<OperatorInput>, OperatorStatus>;
 
 /// The smallest unit of a plan, representing a single step. Contains a system that gets called for you during the execution of the plan.
+///
+/// The system is registered when the operator is first inserted and unregistered when the last operator using it is removed.
+/// Cloning an inserted operator shares its system, so spawning clones is cheap. See [`Operator::shared`] for sharing
+/// a system between operators created separately.
 #[derive(Component, Reflect)]
 #[reflect(Component)]
 #[component(on_insert = Self::on_insert_hook, on_replace = Self::on_replace_hook)]
@@ -20,14 +25,20 @@ pub struct Operator {
     #[reflect(ignore)]
     register_system: Option<Box<dyn FnOnce(&mut Commands) -> OperatorId + Send + Sync>>,
     #[reflect(ignore)]
+    shared_type: Option<TypeId>,
+    #[reflect(ignore)]
     system_id: Option<OperatorId>,
+    #[reflect(ignore)]
+    pub(crate) start_batched: Option<StartBatched>,
 }
 
 impl Clone for Operator {
     fn clone(&self) -> Self {
         Self {
             register_system: None,
+            shared_type: self.shared_type,
             system_id: self.system_id,
+            start_batched: self.start_batched,
         }
     }
 }
@@ -58,10 +69,26 @@ impl Operator {
         let system = IntoSystem::into_system(system);
         Self {
             system_id: None,
+            shared_type: None,
+            start_batched: None,
             register_system: Some(Box::new(move |commands| commands.register_system(system))),
         }
     }
 
+    /// Creates a new operator whose system is shared with all other operators created with the same type of system.
+    /// This is meant for plain functions and closures that don't capture anything, as only the first system of a given type is kept.
+    /// Note that shared systems also share their [`Local`]s.
+    pub fn shared<S, M>(system: S) -> Self
+    where
+        S: IntoSystem<In<OperatorInput>, OperatorStatus, M>,
+        S::System: Send + Sync + 'static,
+    {
+        Self {
+            shared_type: Some(TypeId::of::<S::System>()),
+            ..Self::new(system)
+        }
+    }
+
     /// Shorthand for creating an operator that does nothing.
     pub fn noop() -> Self {
         Self::new(|_: In<OperatorInput>| OperatorStatus::Success)
@@ -73,13 +100,36 @@ impl Operator {
     }
 
     fn on_insert_hook(mut world: DeferredWorld, context: HookContext) {
-        let Some(register_system) = world
-            .get_mut::<Self>(context.entity)
-            .and_then(|mut task_system| task_system.register_system.take())
-        else {
+        let Some(mut operator) = world.get_mut::<Self>(context.entity) else {
             return;
         };
-        let system_id = register_system(&mut world.commands());
+        let register_system = operator.register_system.take();
+        let shared_type = operator.shared_type;
+        let mut system_id = operator.system_id;
+        // Without the `BaePlugin`, systems are neither shared nor reference counted
+        if system_id.is_none()
+            && let Some(shared_type) = shared_type
+        {
+            system_id = world
+                .get_resource::<OperatorSystems>()
+                .and_then(|systems| systems.by_type.get(&shared_type).copied());
+        }
+        let system_id = match (system_id, register_system) {
+            (Some(system_id), _) => system_id,
+            (None, Some(register_system)) => {
+                let system_id = register_system(&mut world.commands());
+                if let Some(shared_type) = shared_type
+                    && let Some(mut systems) = world.get_resource_mut::<OperatorSystems>()
+                {
+                    systems.by_type.insert(shared_type, system_id);
+                }
+                system_id
+            }
+            (None, None) => return,
+        };
+        if let Some(mut systems) = world.get_resource_mut::<OperatorSystems>() {
+            *systems.users.entry(system_id).or_default() += 1;
+        }
         world.get_mut::<Self>(context.entity).unwrap().system_id = Some(system_id);
     }
 
@@ -90,14 +140,56 @@ impl Operator {
         else {
             return;
         };
-        world.commands().unregister_system(system_id);
+        let Some(mut systems) = world.get_resource_mut::<OperatorSystems>() else {
+            // Without the `BaePlugin`, every operator owns its system
+            world.commands().unregister_system(system_id);
+            return;
+        };
+        let Some(users) = systems.users.get_mut(&system_id) else {
+            return;
+        };
+        *users = users.saturating_sub(1);
+        // Defer the check, as the operator may be getting replaced by a clone of itself.
+        world.commands().queue(move |world: &mut World| {
+            let Some(mut systems) = world.get_resource_mut::<OperatorSystems>() else {
+                return;
+            };
+            if systems
+                .users
+                .get(&system_id)
+                .is_some_and(|&users| users > 0)
+            {
+                return;
+            }
+            systems.users.remove(&system_id);
+            systems.by_type.retain(|_, id| *id != system_id);
+            world.unregister_system(system_id).ok();
+        });
     }
 }
 
+/// Reference counts of the systems used by [`Operator`]s.
+#[derive(Resource, Default)]
+pub(crate) struct OperatorSystems {
+    users: HashMap<OperatorId, usize>,
+    by_type: HashMap<TypeId, OperatorId>,
+}
+
 /// Inputs for an operator.
 pub struct OperatorInput {
-    /// The entity up the hierarchy that holds the [`Plan`]. This is usually your entity of interest.
+    /// The entity up the hierarchy that holds the [`Plan`].
     pub entity: Entity,
+    /// The entity the operator acts on. This is usually your entity of interest.
+    /// It is the same as [`OperatorInput::entity`], unless the planner has a [`PlanTarget`](crate::plan::target::PlanTarget)
+    /// or is a plan layer (see [`PlanLayerOf`](crate::plan::layer::PlanLayerOf)).
+    pub actor: Entity,
+    /// The entity whose [`Props`] the plan works with. Usually the same as [`OperatorInput::actor`].
+    pub props: Entity,
+    /// The [`SmartObject`](crate::task::compound::smart_object::SmartObject) that contributed the operator, if any.
+    pub smart_object: Option<Entity>,
     /// The entity that represents the operator itself. Useful if you want to associate custom extra data with an operator.
     pub operator: Entity,
+    /// The time that passed since the agent last ran. This is the delta of [`Time`](bevy_time::Time),
+    /// unless the agent runs less often because of a [`PlanTickRate`](crate::plan::tick_rate::PlanTickRate).
+    pub delta: Duration,
 }
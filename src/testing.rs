@@ -0,0 +1,191 @@
+//! Utilities for testing domains. Requires the `testing` feature.
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use bevy_bae::{prelude::*, testing::*};
+//!
+//! let mut app = test_app();
+//! let agent = app.spawn_agent((
+//!     Select,
+//!     tasks![
+//!         (
+//!             conditions![Condition::eq("tired", true)],
+//!             mock_operator("sleep")
+//!         ),
+//!         (
+//!             scripted_operator("work", [OperatorStatus::Ongoing, OperatorStatus::Success]),
+//!             effects![Effect::set("tired", true)],
+//!         ),
+//!     ],
+//! ));
+//! app.step(1).assert_plan(agent, &["work"]);
+//! app.step(1).assert_prop(agent, "tired", true);
+//! app.step(1).assert_operators_ran(&["work", "work", "sleep"]);
+//! ```
+
+use alloc::sync::Arc;
+
+use bevy_app::TaskPoolPlugin;
+use bevy_time::{Fixed, Time, TimePlugin, TimeUpdateStrategy};
+
+use crate::{condition::prop_or_default, prelude::*};
+
+/// Creates an [`App`] with [`BaePlugin`] and the bare minimum of plugins needed to run it.
+/// Every call to [`App::update`], or one step of [`BaeTestAppExt::step`], runs exactly one tick of [`FixedUpdate`].
+pub fn test_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((TaskPoolPlugin::default(), TimePlugin, BaePlugin::default()))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(
+            Time::<Fixed>::default().timestep(),
+        ))
+        .init_resource::<OperatorLog>();
+    app.finish();
+    app.cleanup();
+    // The very first update does not advance time, so get it out of the way.
+    app.update();
+    app
+}
+
+/// Log of all [`Operator`]s created through [`mock_operator`] and [`scripted_operator`] that ran, in order.
+/// Initialized by [`test_app`].
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct OperatorLog(pub Vec<OperatorLogEntry>);
+
+/// A single run of an [`Operator`] in the [`OperatorLog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorLogEntry {
+    /// The entity up the hierarchy that holds the [`Plan`].
+    pub entity: Entity,
+    /// The entity holding the [`Operator`].
+    pub operator: Entity,
+    /// The name given to the mock operator.
+    pub name: String,
+    /// What the operator returned.
+    pub status: OperatorStatus,
+}
+
+/// Creates a named [`Operator`] that always returns [`OperatorStatus::Success`] and writes to the [`OperatorLog`].
+pub fn mock_operator(name: impl Into<String>) -> impl Bundle {
+    scripted_operator(name, [])
+}
+
+/// Creates a named [`Operator`] that returns the given statuses in order and writes to the [`OperatorLog`].
+/// Once the script is exhausted, the last status is repeated. An empty script always returns [`OperatorStatus::Success`].
+pub fn scripted_operator(
+    name: impl Into<String>,
+    script: impl IntoIterator<Item = OperatorStatus>,
+) -> impl Bundle {
+    let name = name.into();
+    let script: Arc<[OperatorStatus]> = script.into_iter().collect();
+    (
+        Name::new(name.clone()),
+        Operator::new(
+            move |In(input): In<OperatorInput>,
+                  mut log: ResMut<OperatorLog>,
+                  mut step: Local<usize>|
+                  -> OperatorStatus {
+                let status = script
+                    .get(*step)
+                    .or(script.last())
+                    .copied()
+                    .unwrap_or(OperatorStatus::Success);
+                *step += 1;
+                log.push(OperatorLogEntry {
+                    entity: input.entity,
+                    operator: input.operator,
+                    name: name.clone(),
+                    status,
+                });
+                status
+            },
+        ),
+    )
+}
+
+/// Used to allow calling the testing helpers on [`App`].
+pub trait BaeTestAppExt {
+    /// Spawns the given behavior with a [`Plan`] and returns the agent.
+    fn spawn_agent(&mut self, behavior: impl Bundle) -> Entity;
+
+    /// Runs `ticks` updates of the app.
+    fn step(&mut self, ticks: usize) -> &mut Self;
+
+    /// Asserts that the operators left in the [`Plan`] of `agent` have the given [`Name`]s, in order.
+    #[track_caller]
+    fn assert_plan(&mut self, agent: Entity, names: &[&str]) -> &mut Self;
+
+    /// Asserts that the prop `name` of `agent` holds `expected`.
+    #[track_caller]
+    fn assert_prop(
+        &mut self,
+        agent: Entity,
+        name: impl Into<Ustr>,
+        expected: impl Into<Value>,
+    ) -> &mut Self;
+
+    /// Asserts that the [`OperatorLog`] contains the given operator names, in order, and clears it.
+    #[track_caller]
+    fn assert_operators_ran(&mut self, names: &[&str]) -> &mut Self;
+}
+
+impl BaeTestAppExt for App {
+    fn spawn_agent(&mut self, behavior: impl Bundle) -> Entity {
+        self.world_mut()
+            .spawn(behavior)
+            .insert_if_new(Plan::new())
+            .id()
+    }
+
+    fn step(&mut self, ticks: usize) -> &mut Self {
+        for _ in 0..ticks {
+            self.update();
+        }
+        self
+    }
+
+    #[track_caller]
+    fn assert_plan(&mut self, agent: Entity, names: &[&str]) -> &mut Self {
+        let world = self.world();
+        let plan = world
+            .get::<Plan>(agent)
+            .unwrap_or_else(|| panic!("{agent} has no plan"));
+        let actual: Vec<_> = plan
+            .iter()
+            .map(|&idx| name_of(world, plan.nodes[idx].entity))
+            .collect();
+        assert_eq!(actual, names, "unexpected plan for {agent}");
+        self
+    }
+
+    #[track_caller]
+    fn assert_prop(
+        &mut self,
+        agent: Entity,
+        name: impl Into<Ustr>,
+        expected: impl Into<Value>,
+    ) -> &mut Self {
+        let name = name.into();
+        // Only read the props, so that asserting doesn't insert the prop or mark the props as changed
+        let props = self
+            .world()
+            .get::<Props>(agent)
+            .unwrap_or_else(|| panic!("{agent} has no props"));
+        let actual = prop_or_default(props, name);
+        assert_eq!(actual, expected.into(), "unexpected value for prop {name}");
+        self
+    }
+
+    #[track_caller]
+    fn assert_operators_ran(&mut self, names: &[&str]) -> &mut Self {
+        let mut log = self.world_mut().resource_mut::<OperatorLog>();
+        let actual: Vec<_> = log.drain(..).map(|entry| entry.name).collect();
+        assert_eq!(actual, names, "unexpected operators ran");
+        self
+    }
+}
+
+fn name_of(world: &World, entity: Entity) -> String {
+    world
+        .get::<Name>(entity)
+        .map_or_else(|| entity.to_string(), ToString::to_string)
+}
@@ -0,0 +1,94 @@
+//! Tests the planner diagnostics
+
+use bevy::{diagnostic::DiagnosticsStore, prelude::*, time::TimeUpdateStrategy};
+use bevy_bae::{
+    diagnostics::{BaeDiagnosticsPlugin, PlanDiagnostics},
+    plan::async_planning::finish_async_plans,
+    prelude::*,
+};
+
+#[test]
+fn measures_planner() {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        BaePlugin::default(),
+        BaeDiagnosticsPlugin::default(),
+    ))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(
+        Time::<Fixed>::default().timestep(),
+    ));
+    let agent = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            PlanDiagnostics::default(),
+            Select,
+            tasks![
+                (conditions![Condition::always_false()], Operator::noop()),
+                Operator::noop()
+            ],
+        ))
+        .id();
+    app.finish();
+    for _ in 0..3 {
+        app.update();
+    }
+
+    let store = app.world().resource::<DiagnosticsStore>();
+    for path in [
+        BaeDiagnosticsPlugin::REPLANS,
+        BaeDiagnosticsPlugin::DECOMPOSITION_TIME,
+        BaeDiagnosticsPlugin::TASKS_VISITED,
+        BaeDiagnosticsPlugin::OPERATORS_EXECUTED,
+        BaeDiagnosticsPlugin::EXECUTION_TIME,
+    ] {
+        assert!(store.get_measurement(&path).is_some(), "missing {path}");
+    }
+    assert_eq!(
+        store
+            .get_measurement(&BaeDiagnosticsPlugin::TASKS_VISITED)
+            .unwrap()
+            .value,
+        2.0
+    );
+
+    let agent = app.world().get::<PlanDiagnostics>(agent).unwrap();
+    assert!(agent.replans > 0);
+    assert_eq!(agent.replans, agent.operators_executed);
+    assert_eq!(agent.last_tasks_visited, 2);
+}
+
+#[test]
+fn counts_tasks_visited_by_async_planning() {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, BaePlugin::default()))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(
+            Time::<Fixed>::default().timestep(),
+        ));
+    let agent = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            AsyncPlanning,
+            PlanDiagnostics::default(),
+            Select,
+            tasks![
+                (conditions![Condition::always_false()], Operator::noop()),
+                Operator::noop()
+            ],
+        ))
+        .id();
+    app.finish();
+    for _ in 0..10 {
+        finish_async_plans(app.world_mut());
+        app.update();
+        if app.world().get::<PlanDiagnostics>(agent).unwrap().replans > 0 {
+            break;
+        }
+    }
+
+    let agent = app.world().get::<PlanDiagnostics>(agent).unwrap();
+    assert_eq!(agent.replans, 1);
+    assert_eq!(agent.last_tasks_visited, 2);
+}
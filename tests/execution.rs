@@ -1,10 +1,14 @@
 //! Tests the plan execution
 
 use bevy::{log::LogPlugin, prelude::*, time::TimeUpdateStrategy};
-use bevy_bae::prelude::*;
+use bevy_bae::{
+    plan::{async_planning::finish_async_plans, history::PlanEndReason, update::ReplacePlan},
+    prelude::*,
+    task::batched::{Active, BatchedAppExt},
+};
 use bevy_ecs::entity_disabling::Disabled;
 use bevy_mod_props::PropsMutExt;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[test]
 fn runs_plan() {
@@ -383,32 +387,1134 @@ fn logs_plan() {
     app.update();
 }
 
+#[test]
+fn records_plan_history() {
+    let mut app = App::test((
+        PlanHistory::new(2),
+        Select,
+        tasks![
+            (
+                Sequence,
+                tasks![op("a"), (op("b"), cond_is("disabled", false)),]
+            ),
+            op("c"),
+        ],
+    ));
+    app.update();
+    app.assert_last_opt("a");
+    app.behavior_entity().set_prop("disabled", true);
+    app.update();
+    app.assert_last_opt(None);
+    app.update();
+    app.assert_last_opt("c");
+
+    let history = app.behavior_entity().get::<PlanHistory>().unwrap().clone();
+    let names = |entities: Vec<Entity>| -> Vec<String> {
+        entities
+            .into_iter()
+            .map(|entity| app.world().get::<Name>(entity).unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(history.records.len(), 2);
+
+    let aborted = &history.records[0];
+    assert_eq!(names(aborted.planned.clone()), vec!["a", "b"]);
+    assert_eq!(
+        names(
+            aborted
+                .operator_runs
+                .iter()
+                .map(|run| run.operator)
+                .collect()
+        ),
+        vec!["a"]
+    );
+    assert!(matches!(
+        aborted.end.as_ref().unwrap().reason,
+        PlanEndReason::ConditionFailed { .. }
+    ));
+
+    let completed = &history.records[1];
+    assert_eq!(names(completed.planned.clone()), vec!["c"]);
+    assert_eq!(completed.operator_runs[0].status, OperatorStatus::Success);
+    assert_eq!(
+        completed.end.as_ref().unwrap().reason,
+        PlanEndReason::Completed
+    );
+}
+
+#[test]
+fn shares_operator_systems() {
+    fn succeed(_: In<OperatorInput>) -> OperatorStatus {
+        OperatorStatus::Success
+    }
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, BaePlugin::default()));
+    let world = app.world_mut();
+
+    let original = world.spawn(Operator::noop()).id();
+    let id = world.get::<Operator>(original).unwrap().system_id();
+    let clone = world.get::<Operator>(original).unwrap().clone();
+    let clone = world.spawn(clone).id();
+    world.despawn(original);
+    world.flush();
+    let input = OperatorInput {
+        entity: clone,
+        actor: clone,
+        props: clone,
+        smart_object: None,
+        operator: clone,
+        delta: default(),
+    };
+    assert_eq!(
+        world.run_system_with(id, input).unwrap(),
+        OperatorStatus::Success
+    );
+    world.despawn(clone);
+    world.flush();
+    let input = OperatorInput {
+        entity: clone,
+        actor: clone,
+        props: clone,
+        smart_object: None,
+        operator: clone,
+        delta: default(),
+    };
+    assert!(world.run_system_with(id, input).is_err());
+
+    let a = world.spawn(Operator::shared(succeed)).id();
+    let b = world.spawn(Operator::shared(succeed)).id();
+    assert_eq!(world.get::<Operator>(a), world.get::<Operator>(b));
+}
+
+#[test]
+fn registers_operators_without_plugin() {
+    let mut world = World::new();
+    let operator = world.spawn(Operator::noop()).id();
+    world.flush();
+    let id = world.get::<Operator>(operator).unwrap().system_id();
+    let input = OperatorInput {
+        entity: operator,
+        actor: operator,
+        props: operator,
+        smart_object: None,
+        operator,
+        delta: default(),
+    };
+    assert_eq!(
+        world.run_system_with(id, input).unwrap(),
+        OperatorStatus::Success
+    );
+    world.despawn(operator);
+    world.flush();
+    let input = OperatorInput {
+        entity: operator,
+        actor: operator,
+        props: operator,
+        smart_object: None,
+        operator,
+        delta: default(),
+    };
+    assert!(world.run_system_with(id, input).is_err());
+}
+
+#[test]
+fn runs_batched_operators() {
+    struct Wait;
+    let mut app = App::test((
+        Sequence,
+        tasks![(Name::new("wait"), Operator::batched::<Wait>()), op("a")],
+    ));
+    app.add_batched_operator::<Wait>();
+    app.update();
+    app.assert_last_opt(None);
+    assert!(app.behavior_entity().contains::<Active<Wait>>());
+
+    app.update();
+    app.assert_last_opt(None);
+    app.behavior_entity()
+        .get_mut::<Active<Wait>>()
+        .unwrap()
+        .status = OperatorStatus::Success;
+
+    app.update();
+    app.assert_last_opt("a");
+    assert!(!app.behavior_entity().contains::<Active<Wait>>());
+}
+
+#[test]
+#[should_panic(expected = "add `BaePlugin` before `add_batched_operator`")]
+fn requires_plugin_for_batched_operators() {
+    struct Wait;
+    App::new().add_batched_operator::<Wait>();
+}
+
+#[test]
+fn runs_batched_operators_on_actor() {
+    struct Wait;
+    let mut app = App::bare();
+    app.add_batched_operator::<Wait>();
+    let member = app.world_mut().spawn_empty().id();
+    let commander = app
+        .world_mut()
+        .spawn((
+            PlanTarget::new(member),
+            Sequence,
+            tasks![Operator::batched::<Wait>()],
+        ))
+        .id();
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    let active = app.world().get::<Active<Wait>>(member).unwrap();
+    assert_eq!(active.planner, commander);
+    assert!(!app.world().entity(commander).contains::<Active<Wait>>());
+
+    app.world_mut().despawn(commander);
+    app.update();
+    assert!(!app.world().entity(member).contains::<Active<Wait>>());
+}
+
+#[test]
+fn plans_asynchronously() {
+    let mut app = App::test((
+        AsyncPlanning,
+        Select,
+        tasks![(op("a"), cond_is("use_a", true)), op("b")],
+    ));
+    // The plan is still being decomposed in the background
+    assert!(app.behavior_entity().get::<Plan>().unwrap().is_empty());
+    app.update_until_next_operator(None);
+    app.assert_last_opt("b");
+}
+
+#[test]
+fn applies_async_plans_while_a_plan_runs() {
+    let mut app = App::test((
+        AsyncPlanning,
+        Select,
+        tasks![
+            (op("a"), cond_is("use_a", true)),
+            (
+                Name::new("b"),
+                Operator::new(
+                    |_: In<OperatorInput>, mut last_opt: ResMut<LastOpt>| -> OperatorStatus {
+                        last_opt.0 = Some("b".to_string());
+                        OperatorStatus::Ongoing
+                    }
+                ),
+                cond_is("busy", false),
+            ),
+        ],
+    ));
+    app.update_until_next_operator(None);
+    app.assert_last_opt("b");
+
+    // The running plan of `b` is replaced once the background decomposition is done
+    app.behavior_entity().set_prop("use_a", true);
+    let agent = app.behavior_entity().id();
+    app.world_mut().trigger(UpdatePlan::new(agent));
+    app.update_until_next_operator("b");
+    app.assert_last_opt("a");
+}
+
+#[test]
+fn discards_stale_async_plans() {
+    let mut app = App::test((
+        AsyncPlanning,
+        Select,
+        tasks![(op("a"), cond_is("use_a", true)), op("b")],
+    ));
+    // Props that the domain doesn't read don't matter
+    app.behavior_entity().set_prop("unrelated", true);
+    // The decomposition started with `use_a == false`
+    app.behavior_entity().set_prop("use_a", true);
+    app.update_until_next_operator(None);
+    app.assert_last_opt("a");
+}
+
+#[test]
+fn plans_unsupported_domains_synchronously() {
+    let mut app = App::test((
+        AsyncPlanning,
+        Select,
+        tasks![(Repeat::Times(1), tasks![op("a")])],
+    ));
+    // `Repeat` can't be decomposed in the background
+    assert!(!app.behavior_entity().get::<Plan>().unwrap().is_empty());
+    app.update();
+    app.assert_last_opt("a");
+}
+
+#[test]
+fn schedules_replans_by_priority() {
+    let mut app = App::bare();
+    app.insert_resource(ReplanScheduler {
+        max_wait: Some(1),
+        ..ReplanScheduler::new(ReplanBudget::Count(1))
+    });
+    let low = app.world_mut().spawn((Plan::new(), Operator::noop())).id();
+    let high = app
+        .world_mut()
+        .spawn((Plan::new(), ReplanPriority(1), Operator::noop()))
+        .id();
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    let scheduler = app.world().resource::<ReplanScheduler>();
+    assert!(scheduler.is_queued(low));
+    assert!(!scheduler.is_queued(high));
+
+    // `high` wants to replan again, but `low` has waited long enough
+    app.update();
+    let scheduler = app.world().resource::<ReplanScheduler>();
+    assert!(!scheduler.is_queued(low));
+    assert!(scheduler.is_queued(high));
+}
+
+#[test]
+fn protects_against_starvation_by_default() {
+    let scheduler = ReplanScheduler::default();
+    assert_eq!(scheduler.max_wait, Some(60));
+    assert_eq!(
+        scheduler.max_wait,
+        ReplanScheduler::new(ReplanBudget::Unlimited).max_wait
+    );
+}
+
+#[test]
+fn respects_tick_rate() {
+    let mut app = App::test((
+        PlanTickRate::every_nth_tick(2),
+        Operator::new(
+            |input: In<OperatorInput>, mut last_opt: ResMut<LastOpt>| -> OperatorStatus {
+                last_opt.0 = Some(format!("{:?}", input.delta));
+                OperatorStatus::Success
+            },
+        ),
+    ));
+    let timestep = Time::<Fixed>::default().timestep();
+    app.update();
+    app.assert_last_opt(None);
+    app.update();
+    assert_eq!(
+        app.world().resource::<LastOpt>().0,
+        Some(format!("{timestep:?}"))
+    );
+    app.update();
+    app.assert_last_opt(None);
+    app.update();
+    assert_eq!(
+        app.world().resource::<LastOpt>().0,
+        Some(format!("{:?}", timestep * 2))
+    );
+}
+
+#[test]
+fn backs_off_after_failures() {
+    let timestep = Time::<Fixed>::default().timestep();
+    let mut app = App::test((
+        ReplanPolicy {
+            failure_cooldown: timestep * 2,
+            backoff_factor: 2.0,
+            ..default()
+        },
+        Operator::new(
+            |_: In<OperatorInput>, mut last_opt: ResMut<LastOpt>| -> OperatorStatus {
+                last_opt.0 = Some("fail".to_string());
+                OperatorStatus::Failure
+            },
+        ),
+    ));
+    app.update();
+    app.assert_last_opt(None);
+    let mut runs = Vec::new();
+    for _ in 0..7 {
+        app.update();
+        runs.push(app.world().resource::<LastOpt>().0.is_some());
+    }
+    assert_eq!(runs, [true, false, true, false, false, false, true]);
+}
+
+#[test]
+fn pauses_and_steps_plans() {
+    let mut app = App::test((PlanPaused, op("a")));
+    app.update();
+    app.update();
+    app.assert_last_opt(None);
+
+    app.behavior_entity().trigger(StepPlan::new);
+    app.update();
+    app.assert_last_opt("a");
+    app.update();
+    app.assert_last_opt(None);
+
+    app.behavior_entity().remove::<PlanPaused>();
+    app.init_resource::<GlobalPlanPause>();
+    app.update();
+    app.assert_last_opt(None);
+
+    app.world_mut().resource_mut::<GlobalPlanPause>().step();
+    app.update();
+    app.assert_last_opt("a");
+    app.update();
+    app.assert_last_opt(None);
+
+    app.world_mut().remove_resource::<GlobalPlanPause>();
+    app.update();
+    app.assert_last_opt("a");
+}
+
+#[test]
+fn overrides_plans() {
+    #[derive(Resource, Default)]
+    struct OverrideEvents(Vec<String>);
+
+    let mut app = App::test(op("a"));
+    app.init_resource::<OverrideEvents>()
+        .add_observer(
+            |_: On<PlanOverrideStarted>, mut events: ResMut<OverrideEvents>| {
+                events.0.push("started".to_string());
+            },
+        )
+        .add_observer(
+            |ended: On<PlanOverrideEnded>, mut events: ResMut<OverrideEvents>| {
+                events.0.push(format!("{:?}", ended.reason));
+            },
+        );
+    let b = app.world_mut().spawn(op("b")).id();
+    let c = app.world_mut().spawn(op("c")).id();
+    app.update();
+    app.update();
+    app.assert_last_opt("a");
+
+    app.behavior_entity().insert(PlanOverride::new([b, c]));
+    app.update();
+    app.assert_last_opt("b");
+    app.behavior_entity().trigger(UpdatePlan::new);
+    app.update();
+    app.assert_last_opt("c");
+    assert!(!app.behavior_entity().contains::<PlanOverride>());
+    app.update();
+    app.assert_last_opt("a");
+
+    app.behavior_entity().insert(PlanOverride::new([b, c]));
+    app.update();
+    app.assert_last_opt("b");
+    app.behavior_entity().remove::<PlanOverride>();
+    app.update();
+    app.assert_last_opt("a");
+
+    assert_eq!(
+        app.world().resource::<OverrideEvents>().0,
+        ["started", "Completed", "started", "Cancelled"]
+    );
+}
+
+#[test]
+fn runs_plan_layers() {
+    #[derive(Resource, Default)]
+    struct Ran(Vec<&'static str>);
+
+    fn logged(name: &'static str, status: OperatorStatus) -> impl Bundle {
+        (
+            Name::new(name),
+            Operator::new(
+                move |_: In<OperatorInput>, mut ran: ResMut<Ran>| -> OperatorStatus {
+                    ran.0.push(name);
+                    status
+                },
+            ),
+        )
+    }
+
+    let mut app = App::bare();
+    app.init_resource::<Ran>();
+    let agent = app
+        .world_mut()
+        .spawn(plan_layers![
+            (
+                Name::new("legs"),
+                Sequence,
+                tasks![(logged("walk", OperatorStatus::Success), eff("moved", true))],
+                cond_is("moved", false),
+            ),
+            (
+                Name::new("head"),
+                Select,
+                tasks![
+                    (
+                        logged("look", OperatorStatus::Ongoing),
+                        cond_is("moved", true)
+                    ),
+                    logged("idle", OperatorStatus::Ongoing),
+                ],
+            ),
+        ])
+        .id();
+    // The very first update does not advance time
+    app.update();
+    for _ in 0..3 {
+        app.update();
+    }
+
+    let ran = &app.world().resource::<Ran>().0;
+    assert_eq!(ran.iter().filter(|&&name| name == "walk").count(), 1);
+    assert_eq!(ran.last(), Some(&"look"));
+    // The layers share the props of the agent
+    assert!(*app.world_mut().entity_mut(agent).get_prop::<bool>("moved"));
+    let layers = app.world().get::<PlanLayers>(agent).unwrap();
+    let head = layers.named(app.world(), "head").unwrap();
+    assert!(!*app.world_mut().entity_mut(head).get_prop::<bool>("moved"));
+}
+
+#[test]
+fn plans_for_target() {
+    let mut app = App::bare();
+    let member = app.world_mut().spawn(Props::default()).id();
+    app.world_mut().entity_mut(member).set_prop("ready", true);
+    let commander = app
+        .world_mut()
+        .spawn((
+            PlanTarget::new(member).with_props(member),
+            Select,
+            tasks![(
+                Operator::new(
+                    move |input: In<OperatorInput>,
+                          mut last_opt: ResMut<LastOpt>|
+                          -> OperatorStatus {
+                        if input.actor == member && input.props == member {
+                            last_opt.0 = Some("order".to_string());
+                        }
+                        OperatorStatus::Success
+                    }
+                ),
+                cond_is("ready", true),
+                eff("done", true),
+            )],
+        ))
+        .id();
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    app.assert_last_opt("order");
+    assert!(*app.world_mut().entity_mut(member).get_prop::<bool>("done"));
+    assert!(
+        !*app
+            .world_mut()
+            .entity_mut(commander)
+            .get_prop::<bool>("done")
+    );
+}
+
+#[test]
+fn delegates_to_members() {
+    let mut app = App::bare();
+    let broken = app
+        .world_mut()
+        .spawn((Select, tasks![(op("never"), cond_is("go", true))]))
+        .id();
+    let member = app
+        .world_mut()
+        .spawn((
+            Select,
+            tasks![(op("work"), cond_is("go", true), eff("go", false))],
+        ))
+        .id();
+    app.world_mut().entity_mut(broken).set_prop("go", false);
+    app.world_mut().entity_mut(member).set_prop("go", false);
+    let squad = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Select,
+            tasks![
+                (
+                    Sequence,
+                    cond_is("failed", false),
+                    tasks![
+                        Delegate::new(broken).with_failure_prop("failed"),
+                        op("unreachable"),
+                    ],
+                ),
+                (
+                    Sequence,
+                    cond_is("finished", false),
+                    tasks![
+                        Delegate::new(member).with_prop("go", true),
+                        (op("done"), eff("finished", true)),
+                    ],
+                ),
+            ],
+        ))
+        .id();
+    app.world_mut().entity_mut(squad).set_prop("failed", false);
+    app.world_mut()
+        .entity_mut(squad)
+        .set_prop("finished", false);
+    // The very first update does not advance time
+    app.update();
+    for _ in 0..8 {
+        app.update();
+    }
+
+    app.assert_last_opt("done");
+    assert!(*app.world_mut().entity_mut(squad).get_prop::<bool>("failed"));
+    assert!(!*app.world_mut().entity_mut(member).get_prop::<bool>("go"));
+}
+
+#[test]
+fn delegates_tasks_to_members() {
+    #[derive(Resource, Default)]
+    struct Ran(Vec<Entity>);
+
+    let mut app = App::bare();
+    app.init_resource::<Ran>();
+    let task = app
+        .world_mut()
+        .spawn((
+            Select,
+            tasks![Operator::new(
+                |input: In<OperatorInput>, mut ran: ResMut<Ran>| -> OperatorStatus {
+                    ran.0.push(input.actor);
+                    OperatorStatus::Success
+                }
+            )],
+        ))
+        .id();
+    let first = app.world_mut().spawn(Props::default()).id();
+    let second = app.world_mut().spawn(Props::default()).id();
+    let squad = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Select,
+            tasks![(
+                Sequence,
+                cond_is("finished", false),
+                tasks![
+                    Delegate::new(first).with_task(task),
+                    Delegate::new(second).with_task(task),
+                    (op("done"), eff("finished", true)),
+                ],
+            )],
+        ))
+        .id();
+    app.world_mut()
+        .entity_mut(squad)
+        .set_prop("finished", false);
+    // The very first update does not advance time
+    app.update();
+    for _ in 0..8 {
+        app.update();
+    }
+
+    app.assert_last_opt("done");
+    assert_eq!(app.world().resource::<Ran>().0, [first, second]);
+    let task = app.world().entity(task);
+    assert!(!task.contains::<Plan>());
+    assert!(!task.contains::<PlanTarget>());
+}
+
+#[test]
+fn cancels_delegated_tasks() {
+    let mut app = App::bare();
+    let task = app
+        .world_mut()
+        .spawn((
+            Select,
+            tasks![Operator::new(|_: In<OperatorInput>| {
+                OperatorStatus::Ongoing
+            })],
+        ))
+        .id();
+    let member = app.world_mut().spawn(Props::default()).id();
+    let squad = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Select,
+            tasks![Delegate::new(member).with_task(task)],
+        ))
+        .id();
+    // The very first update does not advance time
+    app.update();
+    app.update();
+    assert!(app.world().entity(task).contains::<PlanTarget>());
+
+    app.world_mut().entity_mut(squad).insert(Plan::default());
+    app.world_mut().flush();
+    let task = app.world().entity(task);
+    assert!(!task.contains::<Plan>());
+    assert!(!task.contains::<PlanTarget>());
+}
+
+#[test]
+fn respects_reservations() {
+    let mut app = App::bare();
+    app.init_resource::<Crossings>();
+    let goblin = || {
+        (
+            Plan::new(),
+            Select,
+            tasks![
+                (cross("bridge"), Reserve::new(["bridge"])),
+                (cross("ford"), conditions![Condition::not_reserved("ford")]),
+            ],
+        )
+    };
+    let first = app.world_mut().spawn(goblin()).id();
+    let second = app.world_mut().spawn(goblin()).id();
+    // The very first update does not advance time
+    app.update();
+    app.update();
+
+    let mut ran = app.world().resource::<Crossings>().0.clone();
+    ran.sort();
+    assert_eq!(ran, [(first, "bridge"), (second, "ford")]);
+    let reservations = app.world().resource::<Reservations>();
+    assert_eq!(reservations.owner("bridge"), Some(first));
+    assert!(reservations.is_reserved_by_other("bridge", second));
+    // Reservations are not written into the props of the agents
+    for goblin in [first, second] {
+        let props = app.world().get::<Props>(goblin);
+        assert!(props.is_none_or(|props| props.iter().next().is_none()));
+    }
+
+    app.world_mut().despawn(first);
+    let reservations = app.world().resource::<Reservations>();
+    assert_eq!(reservations.owner("bridge"), None);
+}
+
+#[test]
+fn rejects_plans_with_lost_reservations() {
+    let mut app = App::bare();
+    app.init_resource::<Crossings>();
+    // Both goblins decompose at the same time, so both see the bridge as free
+    let goblin = || {
+        (
+            Plan::new(),
+            AsyncPlanning,
+            Select,
+            tasks![
+                (
+                    Name::new("bridge"),
+                    cross("bridge"),
+                    Reserve::new(["bridge"])
+                ),
+                cross("ford"),
+            ],
+        )
+    };
+    let first = app.world_mut().spawn(goblin()).id();
+    let second = app.world_mut().spawn(goblin()).id();
+    let both_crossed = |app: &App| {
+        let ran = &app.world().resource::<Crossings>().0;
+        [first, second]
+            .iter()
+            .all(|agent| ran.iter().any(|(entity, _)| entity == agent))
+    };
+    for _ in 0..10 {
+        finish_async_plans(app.world_mut());
+        app.update();
+        if both_crossed(&app) {
+            break;
+        }
+    }
+    assert!(
+        both_crossed(&app),
+        "both goblins should have crossed within 10 updates"
+    );
+
+    let ran = &app.world().resource::<Crossings>().0;
+    let bridge: Vec<_> = ran
+        .iter()
+        .filter(|(_, name)| *name == "bridge")
+        .map(|(entity, _)| *entity)
+        .collect();
+    assert!(!bridge.is_empty());
+    assert!(bridge.iter().all(|&entity| entity == bridge[0]));
+    let owner = app.world().resource::<Reservations>().owner("bridge");
+    assert_eq!(owner, Some(bridge[0]));
+}
+
+#[test]
+fn does_not_cache_plans_with_reservations() {
+    let mut app = App::bare();
+    app.init_resource::<Crossings>()
+        .init_resource::<PlanCache>();
+    let domain = app
+        .world_mut()
+        .spawn((
+            Select,
+            tasks![(cross("bridge"), Reserve::new(["bridge"])), cross("ford"),],
+        ))
+        .id();
+    let first = app.world_mut().spawn((Plan::new(), TaskRef(domain))).id();
+    let second = app.world_mut().spawn((Plan::new(), TaskRef(domain))).id();
+    // The very first update does not advance time
+    app.update();
+    app.update();
+
+    let mut ran = app.world().resource::<Crossings>().0.clone();
+    ran.sort();
+    assert_eq!(ran, [(first, "bridge"), (second, "ford")]);
+    assert!(app.world().resource::<PlanCache>().is_empty());
+}
+
+#[test]
+fn uses_smart_objects() {
+    #[derive(Component)]
+    struct Goblin;
+
+    #[derive(Resource, Default)]
+    struct Used(Option<(&'static str, Option<Entity>)>);
+
+    fn use_object(name: &'static str) -> impl Bundle {
+        Operator::new(
+            move |input: In<OperatorInput>, mut used: ResMut<Used>| -> OperatorStatus {
+                used.0 = Some((name, input.smart_object));
+                OperatorStatus::Success
+            },
+        )
+    }
+
+    let mut app = App::test((Select, tasks![SmartObjectSlot, op("idle")]));
+    app.init_resource::<Used>();
+    app.world_mut().spawn((
+        SmartObject::with_eligibility(|agent, _| agent.contains::<Goblin>()),
+        tasks![use_object("resupply")],
+    ));
+    let bench = app
+        .world_mut()
+        .spawn((SmartObject::new(), tasks![use_object("sit")]))
+        .id();
+    app.update();
+
+    app.assert_last_opt(None);
+    assert_eq!(app.world().resource::<Used>().0, Some(("sit", Some(bench))));
+}
+
+#[test]
+fn plugs_slots() {
+    let mut app = App::test((Select, tasks![Slot, op("idle")]));
+    let sword = app
+        .world_mut()
+        .spawn(Operator::new(
+            |_: In<OperatorInput>, mut last_opt: ResMut<LastOpt>| -> OperatorStatus {
+                last_opt.0 = Some("swing".to_string());
+                OperatorStatus::Ongoing
+            },
+        ))
+        .id();
+    app.update();
+    app.assert_last_opt("idle");
+
+    let slot = app
+        .world_mut()
+        .query_filtered::<Entity, With<Slot>>()
+        .single(app.world())
+        .unwrap();
+    app.world_mut().commands().entity(slot).plug(sword);
+    app.world_mut().flush();
+    app.update();
+    app.assert_last_opt("swing");
+    app.update();
+    app.assert_last_opt("swing");
+
+    app.world_mut().commands().entity(slot).unplug();
+    app.world_mut().flush();
+    assert!(app.behavior_entity().get::<Plan>().unwrap().is_empty());
+    app.update();
+    app.assert_last_opt("idle");
+}
+
+#[test]
+fn unplugs_referenced_slots() {
+    let mut app = App::bare();
+    let weapons = app
+        .world_mut()
+        .spawn((Select, tasks![Slot, op("idle")]))
+        .id();
+    let slot = app.world().get::<Tasks>(weapons).unwrap()[0];
+    let sword = app
+        .world_mut()
+        .spawn(Operator::new(|_: In<OperatorInput>| {
+            OperatorStatus::Ongoing
+        }))
+        .id();
+    app.world_mut().commands().entity(slot).plug(sword);
+    let agent = app
+        .world_mut()
+        .spawn((Plan::new(), PlanHistory::new(1), TaskRef(weapons)))
+        .id();
+    // The very first update does not advance time
+    app.update();
+    app.update();
+    assert!(!app.world().get::<Plan>(agent).unwrap().is_empty());
+
+    app.world_mut().commands().entity(slot).unplug();
+    app.world_mut().flush();
+    assert!(app.world().get::<Plan>(agent).unwrap().is_empty());
+    let history = app.world().get::<PlanHistory>(agent).unwrap();
+    assert_eq!(
+        history.records[0].end.as_ref().unwrap().reason,
+        PlanEndReason::OperatorFailed { operator: sword }
+    );
+}
+
+#[test]
+fn references_tasks() {
+    let mut app = App::test((
+        Select,
+        tasks![
+            (TaskRef(Entity::PLACEHOLDER), cond_is("hurt", true)),
+            op("idle")
+        ],
+    ));
+    let shared = app
+        .world_mut()
+        .spawn((
+            Select,
+            tasks![(op("flee"), cond_is("cornered", false)), op("fight")],
+        ))
+        .id();
+    let reference = app
+        .world_mut()
+        .query_filtered::<Entity, With<TaskRef>>()
+        .single(app.world())
+        .unwrap();
+    app.world_mut()
+        .entity_mut(reference)
+        .insert(TaskRef(shared));
+    app.update();
+    app.assert_last_opt("idle");
+
+    app.behavior_entity().set_prop("hurt", true);
+    app.update();
+    app.assert_last_opt("flee");
+
+    app.behavior_entity().set_prop("cornered", true);
+    app.update();
+    app.assert_last_opt("fight");
+
+    // A reference to the root forms a cycle, which stops at the depth limit instead of recursing forever
+    let root = app.behavior_entity().id();
+    app.world_mut().entity_mut(reference).insert(TaskRef(root));
+    app.update();
+    app.assert_last_opt("idle");
+}
+
+#[test]
+fn repeats_tasks() {
+    let mut app = App::test((Repeat::Times(2), tasks![op("a")]));
+    app.update();
+    app.assert_last_opt("a");
+    assert_eq!(operators_planned(&mut app), 2);
+    app.update();
+    app.assert_last_opt("a");
+}
+
+#[test]
+fn repeats_tasks_while_condition_holds() {
+    let mut app = App::test((
+        Select,
+        tasks![
+            (
+                Repeat::While(Condition::in_range("items", ..3.0)),
+                tasks![(op("take"), effects![Effect::inc::<f32>("items", 1.0)])],
+            ),
+            op("idle"),
+        ],
+    ));
+    app.update();
+    app.assert_last_opt("take");
+    assert_eq!(operators_planned(&mut app), 3);
+    app.update();
+    app.assert_last_opt("take");
+    app.update();
+    app.assert_last_opt("take");
+    app.update();
+    app.assert_last_opt("idle");
+
+    // Looping more often than allowed fails the loop
+    let repeat = app
+        .world_mut()
+        .query_filtered::<Entity, With<Repeat>>()
+        .single(app.world())
+        .unwrap();
+    app.world_mut().entity_mut(repeat).insert(MaxRepetitions(2));
+    app.behavior_entity().set_prop("items", 0.0_f32);
+    app.update();
+    app.assert_last_opt("idle");
+}
+
+#[test]
+fn caches_plans() {
+    let mut app = App::bare();
+    app.init_resource::<PlanCache>();
+    let agent = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Select,
+            tasks![(op("a"), cond_is("use_a", true)), op("b")],
+        ))
+        .id();
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    app.assert_last_opt("b");
+    let cache = app.world().resource::<PlanCache>();
+    assert_eq!((cache.hits(), cache.misses()), (0, 1));
+
+    // Props that are not read by the domain don't matter
+    app.world_mut()
+        .entity_mut(agent)
+        .set_prop("unrelated", true);
+    app.update();
+    app.assert_last_opt("b");
+    let cache = app.world().resource::<PlanCache>();
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    app.world_mut().entity_mut(agent).set_prop("use_a", true);
+    app.update();
+    app.assert_last_opt("a");
+    let cache = app.world().resource::<PlanCache>();
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+    // Changing the domain invalidates its cached plans
+    app.world_mut().spawn((op("c"), TaskOf(agent)));
+    assert!(app.world().resource::<PlanCache>().is_empty());
+}
+
+#[test]
+fn invalidates_plans_referencing_changed_domains() {
+    let mut app = App::bare();
+    app.init_resource::<PlanCache>();
+    let shared = app.world_mut().spawn((Select, tasks![op("a")])).id();
+    app.world_mut()
+        .spawn((Plan::new(), Select, tasks![TaskRef(shared)]));
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    app.assert_last_opt("a");
+    assert_eq!(app.world().resource::<PlanCache>().len(), 1);
+
+    // Changing the referenced domain invalidates the plans of the referencing one
+    app.world_mut().spawn((op("b"), TaskOf(shared)));
+    assert!(app.world().resource::<PlanCache>().is_empty());
+}
+
+#[test]
+fn does_not_cache_smart_object_plans() {
+    let mut app = App::bare();
+    app.init_resource::<PlanCache>();
+    app.world_mut()
+        .spawn((Plan::new(), Select, tasks![SmartObjectSlot, op("idle")]));
+    // The very first update does not advance time
+    app.update();
+
+    app.update();
+    app.assert_last_opt("idle");
+
+    // A cached plan would keep the agent idling
+    app.world_mut()
+        .spawn((SmartObject::new(), tasks![op("sit")]));
+    app.update();
+    app.assert_last_opt("sit");
+    let cache = app.world().resource::<PlanCache>();
+    assert!(cache.is_empty());
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+}
+
+#[test]
+fn repairs_plan() {
+    let mut app = App::test((
+        PlanRepair,
+        Sequence,
+        tasks![
+            op("a"),
+            (Select, tasks![(op("b"), cond_is("use_c", false)), op("c")]),
+            op("d"),
+        ],
+    ));
+    app.update();
+    app.assert_last_opt("a");
+    app.behavior_entity().set_prop("use_c", true);
+
+    // `b` can't run anymore, so only the select is decomposed again
+    app.update();
+    app.assert_last_opt(None);
+    app.update();
+    app.assert_last_opt("c");
+    app.update();
+    app.assert_last_opt("d");
+}
+
+#[test]
+fn repairs_referenced_subtrees() {
+    let mut app = App::test((
+        PlanRepair,
+        Sequence,
+        tasks![op("a"), TaskRef(Entity::PLACEHOLDER), op("d")],
+    ));
+    let shared = app
+        .world_mut()
+        .spawn((Select, tasks![(op("b"), cond_is("use_c", false)), op("c")]))
+        .id();
+    let reference = app
+        .world_mut()
+        .query_filtered::<Entity, With<TaskRef>>()
+        .single(app.world())
+        .unwrap();
+    app.world_mut()
+        .entity_mut(reference)
+        .insert(TaskRef(shared));
+    let replaced = Arc::new(Mutex::new(0));
+    app.behavior_entity().observe({
+        let replaced = replaced.clone();
+        move |_: On<ReplacePlan>| *replaced.lock().unwrap() += 1
+    });
+    app.update();
+    app.assert_last_opt("a");
+    app.behavior_entity().set_prop("use_c", true);
+
+    // The referenced select is not below the root in the `Tasks` hierarchy, but it is in the plan
+    app.update();
+    app.assert_last_opt(None);
+    assert_eq!(*replaced.lock().unwrap(), 2);
+    app.update();
+    app.assert_last_opt("c");
+    app.update();
+    app.assert_last_opt("d");
+}
+
 trait TestApp {
     fn test(behavior: impl Bundle) -> App;
+    fn bare() -> App;
     #[track_caller]
     fn assert_last_opt(&self, name: impl Into<Option<&'static str>>);
     fn behavior_entity(&mut self) -> EntityWorldMut<'_>;
+    fn update_until_next_operator(&mut self, running: impl Into<Option<&'static str>>);
 }
 
 impl TestApp for App {
     fn test(behavior: impl Bundle) -> App {
-        let mut app = App::new();
+        let mut app = App::bare();
         let behavior = Mutex::new(Some(behavior));
-        app.add_plugins((
-            MinimalPlugins,
-            LogPlugin {
-                filter: format!(
-                    "bevy_log=off,bevy_bae=debug,{default}",
-                    default = bevy::log::DEFAULT_FILTER
-                ),
-                ..default()
-            },
-            BaePlugin::default(),
-        ))
-        .insert_resource(TimeUpdateStrategy::ManualDuration(
-            Time::<Fixed>::default().timestep(),
-        ))
-        .init_resource::<LastOpt>()
+        app.add_plugins(LogPlugin {
+            filter: format!(
+                "bevy_log=off,bevy_bae=debug,{default}",
+                default = bevy::log::DEFAULT_FILTER
+            ),
+            ..default()
+        })
         .add_systems(Startup, move |mut commands: Commands| {
             commands
                 .spawn(behavior.lock().unwrap().take().unwrap())
@@ -424,6 +1530,18 @@ impl TestApp for App {
         app
     }
 
+    /// An app with just the [`BaePlugin`], for tests that spawn their agents themselves.
+    /// The very first update does not advance time.
+    fn bare() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, BaePlugin::default()))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(
+                Time::<Fixed>::default().timestep(),
+            ))
+            .init_resource::<LastOpt>();
+        app
+    }
+
     #[track_caller]
     fn assert_last_opt(&self, expected: impl Into<Option<&'static str>>) {
         let expected: Option<&'static str> = expected.into();
@@ -441,6 +1559,21 @@ impl TestApp for App {
             .unwrap();
         self.world_mut().entity_mut(entity)
     }
+
+    /// Updates until an operator other than `running` ran, finishing background planning before every update.
+    #[track_caller]
+    fn update_until_next_operator(&mut self, running: impl Into<Option<&'static str>>) {
+        let running = running.into();
+        for _ in 0..10 {
+            finish_async_plans(self.world_mut());
+            self.update();
+            let last_opt = self.world().resource::<LastOpt>().0.as_deref();
+            if last_opt.is_some() && last_opt != running {
+                return;
+            }
+        }
+        panic!("no operator other than {running:?} ran within 10 updates");
+    }
 }
 // The following functions are not reflective of real user code and are here to make the test suite more simple to set up.
 
@@ -460,6 +1593,26 @@ fn op(name: &str) -> impl Bundle {
     )
 }
 
+/// The crossings made by the agents running [`cross`] operators.
+#[derive(Resource, Default)]
+struct Crossings(Vec<(Entity, &'static str)>);
+
+/// An operator that records a crossing of `name` in [`Crossings`] and never finishes.
+fn cross(name: &'static str) -> impl Bundle {
+    Operator::new(
+        move |input: In<OperatorInput>, mut crossings: ResMut<Crossings>| -> OperatorStatus {
+            crossings.0.push((input.entity, name));
+            OperatorStatus::Ongoing
+        },
+    )
+}
+
+fn operators_planned(app: &mut App) -> usize {
+    let entity = app.behavior_entity();
+    let plan = entity.get::<Plan>().unwrap();
+    plan.nodes.iter().filter(|node| !node.composite).count()
+}
+
 fn cond_is(name: &str, val: impl Into<Value>) -> impl Bundle {
     conditions![Condition::eq(name, val)]
 }
@@ -0,0 +1,106 @@
+//! Tests recording and replaying agent decisions
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use bevy_bae::{
+    prelude::*,
+    record::{DecisionRecording, DecisionReplay, RecordDecisions},
+};
+
+#[test]
+fn replays_recorded_decisions() {
+    let mut app = app();
+    app.insert_resource(DecisionRecording::default());
+    let agent = app.world_mut().spawn((behavior(), RecordDecisions)).id();
+    app.update();
+    // Something outside of the planner sets a prop.
+    app.world_mut().entity_mut(agent).set_prop("alert", true);
+    app.update();
+    assert_eq!(app.world().resource::<OperatorRuns>().0, ["patrol", "flee"]);
+
+    let path = std::env::temp_dir().join("bevy_bae_replays_recorded_decisions.ron");
+    app.world()
+        .resource::<DecisionRecording>()
+        .save(&path)
+        .unwrap();
+    let recording = DecisionRecording::load(&path).unwrap();
+    assert_eq!(&recording, app.world().resource::<DecisionRecording>());
+
+    let mut app = self::app();
+    app.insert_resource(DecisionReplay::new(recording));
+    let agent = app.world_mut().spawn(behavior()).id();
+    app.update();
+    app.update();
+    // The operators were replaced by their recorded statuses
+    assert!(app.world().resource::<OperatorRuns>().0.is_empty());
+    // and the recorded props were used for planning.
+    assert!(*app.world_mut().entity_mut(agent).get_prop::<bool>("alert"));
+    assert!(app.world().resource::<DecisionReplay>().is_finished());
+}
+
+#[test]
+fn replays_props_of_plan_targets() {
+    let mut app = app();
+    app.insert_resource(DecisionRecording::default());
+    let member = app.world_mut().spawn_empty().id();
+    app.world_mut().spawn((
+        behavior(),
+        RecordDecisions,
+        PlanTarget::new(member).with_props(member),
+    ));
+    app.update();
+    app.world_mut().entity_mut(member).set_prop("alert", true);
+    app.update();
+    assert_eq!(app.world().resource::<OperatorRuns>().0, ["patrol", "flee"]);
+    let recording = app.world().resource::<DecisionRecording>().clone();
+
+    let mut app = self::app();
+    app.insert_resource(DecisionReplay::new(recording));
+    let member = app.world_mut().spawn_empty().id();
+    app.world_mut()
+        .spawn((behavior(), PlanTarget::new(member).with_props(member)));
+    app.update();
+    app.update();
+    assert!(app.world().resource::<OperatorRuns>().0.is_empty());
+    // The recorded props were restored onto the props source
+    assert!(*app.world_mut().entity_mut(member).get_prop::<bool>("alert"));
+    assert!(app.world().resource::<DecisionReplay>().is_finished());
+}
+
+fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, BaePlugin::default()))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(
+            Time::<Fixed>::default().timestep(),
+        ))
+        .init_resource::<OperatorRuns>();
+    app.finish();
+    app.update();
+    app
+}
+
+#[derive(Resource, Default)]
+struct OperatorRuns(Vec<&'static str>);
+
+fn behavior() -> impl Bundle {
+    (
+        Name::new("npc"),
+        Plan::new(),
+        Select,
+        tasks![
+            (conditions![Condition::eq("alert", true)], op("flee")),
+            op("patrol"),
+        ],
+    )
+}
+
+fn op(name: &'static str) -> impl Bundle {
+    (
+        Name::new(name),
+        Operator::new(
+            move |_: In<OperatorInput>, mut runs: ResMut<OperatorRuns>| {
+                runs.0.push(name);
+                OperatorStatus::Success
+            },
+        ),
+    )
+}
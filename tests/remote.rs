@@ -0,0 +1,222 @@
+//! Tests the Bevy Remote Protocol methods
+
+use bevy::prelude::*;
+use bevy_bae::{
+    prelude::*,
+    remote::{
+        BRP_BAE_CLEAR_PLAN_METHOD, BRP_BAE_GET_AGENT_METHOD, BRP_BAE_LIST_AGENTS_METHOD,
+        BRP_BAE_SET_PROP_METHOD, BaeRemotePlugin,
+    },
+};
+use bevy_remote::{BrpMessage, BrpResult, BrpSender, RemotePlugin};
+use serde_json::{Value as JsonValue, json};
+
+#[test]
+fn lists_agents() {
+    let mut app = app();
+    let agent = app
+        .world_mut()
+        .spawn((Name::new("npc"), Plan::new(), Operator::noop()))
+        .id();
+    app.world_mut().spawn(Name::new("not an agent"));
+
+    let agents = request(&mut app, BRP_BAE_LIST_AGENTS_METHOD, None).unwrap();
+    assert_eq!(agents, json!([{ "entity": agent, "name": "npc" }]));
+}
+
+#[test]
+fn gets_agent() {
+    let mut app = app();
+    let agent = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Sequence,
+            tasks![(Name::new("a"), Operator::noop())],
+        ))
+        .id();
+    app.world_mut().entity_mut(agent).set_prop("alert", true);
+    app.world_mut().entity_mut(agent).set_prop("ammo", 3.0_f32);
+    app.world_mut().entity_mut(agent).set_prop("target", "door");
+    app.world_mut().trigger(UpdatePlan::new(agent));
+    app.world_mut().flush();
+
+    let response = request(
+        &mut app,
+        BRP_BAE_GET_AGENT_METHOD,
+        Some(json!({ "entity": agent })),
+    )
+    .unwrap();
+    assert_eq!(response["operators_left"].as_array().unwrap().len(), 1);
+    assert_eq!(response["domain"]["kind"], "compound");
+    assert_eq!(response["domain"]["tasks"][0]["kind"], "operator");
+    assert_eq!(response["domain"]["tasks"][0]["name"], "a");
+    assert_eq!(response["props"]["alert"], json!(true));
+    assert_eq!(response["props"]["ammo"], json!(3.0));
+    assert_eq!(response["props"]["target"], json!("door"));
+}
+
+#[test]
+fn describes_references_and_smart_objects() {
+    let mut app = app();
+    let shared = app
+        .world_mut()
+        .spawn((Name::new("shared"), Select, tasks![SmartObjectSlot]))
+        .id();
+    app.world_mut().spawn((
+        Name::new("bench"),
+        SmartObject::new(),
+        tasks![(Name::new("sit"), Operator::noop())],
+    ));
+    let agent = app
+        .world_mut()
+        .spawn((
+            Plan::new(),
+            Select,
+            tasks![TaskRef(shared), TaskRef(shared)],
+        ))
+        .id();
+    app.world_mut()
+        .entity_mut(shared)
+        .with_related::<TaskOf>(TaskRef(agent));
+
+    let response = request(
+        &mut app,
+        BRP_BAE_GET_AGENT_METHOD,
+        Some(json!({ "entity": agent })),
+    )
+    .unwrap();
+    let reference = &response["domain"]["tasks"][0];
+    assert_eq!(reference["kind"], "reference");
+    let shared = &reference["tasks"][0];
+    assert_eq!(shared["name"], "shared");
+    let slot = &shared["tasks"][0];
+    assert_eq!(slot["kind"], "smart_object_slot");
+    assert_eq!(slot["tasks"][0]["name"], "bench");
+    assert_eq!(slot["tasks"][0]["tasks"][0]["name"], "sit");
+    // References back to the agent don't recurse forever
+    let back = &shared["tasks"][1]["tasks"][0];
+    assert_eq!(back["recursive"], json!(true));
+    // Shared subtrees are described everywhere they are referenced
+    assert_eq!(response["domain"]["tasks"][1]["tasks"][0]["name"], "shared");
+}
+
+#[test]
+fn sets_prop_and_clears_plan() {
+    let mut app = app();
+    let agent = app.world_mut().spawn((Plan::new(), Operator::noop())).id();
+    app.world_mut().trigger(UpdatePlan::new(agent));
+    app.world_mut().flush();
+    assert!(!app.world().get::<Plan>(agent).unwrap().is_empty());
+
+    request(
+        &mut app,
+        BRP_BAE_SET_PROP_METHOD,
+        Some(json!({ "entity": agent, "name": "alert", "value": true })),
+    )
+    .unwrap();
+    assert!(*app.world_mut().entity_mut(agent).get_prop::<bool>("alert"));
+
+    request(
+        &mut app,
+        BRP_BAE_CLEAR_PLAN_METHOD,
+        Some(json!({ "entity": agent })),
+    )
+    .unwrap();
+    assert!(app.world().get::<Plan>(agent).unwrap().is_empty());
+}
+
+#[test]
+fn uses_props_of_plan_targets() {
+    let mut app = app();
+    let member = app.world_mut().spawn_empty().id();
+    let agent = app
+        .world_mut()
+        .spawn((Operator::noop(), PlanTarget::new(member).with_props(member)))
+        .id();
+
+    request(
+        &mut app,
+        BRP_BAE_SET_PROP_METHOD,
+        Some(json!({ "entity": agent, "name": "alert", "value": true })),
+    )
+    .unwrap();
+    assert!(*app.world_mut().entity_mut(member).get_prop::<bool>("alert"));
+
+    let response = request(
+        &mut app,
+        BRP_BAE_GET_AGENT_METHOD,
+        Some(json!({ "entity": agent })),
+    )
+    .unwrap();
+    assert_eq!(response["props"]["alert"], json!(true));
+}
+
+#[test]
+fn rejects_imprecise_numbers() {
+    let mut app = app();
+    let agent = app.world_mut().spawn((Plan::new(), Operator::noop())).id();
+
+    request(
+        &mut app,
+        BRP_BAE_SET_PROP_METHOD,
+        Some(json!({ "entity": agent, "name": "ammo", "value": 16_777_216 })),
+    )
+    .unwrap();
+    assert_eq!(
+        *app.world_mut().entity_mut(agent).get_prop::<f32>("ammo"),
+        16_777_216.0
+    );
+
+    let response = request(
+        &mut app,
+        BRP_BAE_SET_PROP_METHOD,
+        Some(json!({ "entity": agent, "name": "ammo", "value": 16_777_217 })),
+    );
+    assert!(response.is_err());
+    let response = request(
+        &mut app,
+        BRP_BAE_SET_PROP_METHOD,
+        Some(json!({ "entity": agent, "name": "ammo", "value": 0.1 })),
+    );
+    assert!(response.is_err());
+}
+
+#[test]
+fn rejects_non_agents() {
+    let mut app = app();
+    let entity = app.world_mut().spawn_empty().id();
+    let response = request(
+        &mut app,
+        BRP_BAE_GET_AGENT_METHOD,
+        Some(json!({ "entity": entity })),
+    );
+    assert!(response.is_err());
+}
+
+fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        BaePlugin::default(),
+        RemotePlugin::default(),
+        BaeRemotePlugin,
+    ));
+    app.finish();
+    app
+}
+
+/// Sends a request through the [`RemotePlugin`] and runs the app until it is answered.
+fn request(app: &mut App, method: &str, params: Option<JsonValue>) -> BrpResult {
+    let (sender, receiver) = async_channel::bounded(1);
+    app.world()
+        .resource::<BrpSender>()
+        .try_send(BrpMessage {
+            method: method.to_string(),
+            params,
+            sender,
+        })
+        .unwrap();
+    app.update();
+    receiver.try_recv().unwrap()
+}
@@ -0,0 +1,57 @@
+//! Tests the testing utilities
+
+use bevy::prelude::*;
+use bevy_bae::{prelude::*, testing::*};
+
+#[test]
+fn mock_operators_are_logged_in_order() {
+    let mut app = test_app();
+    let agent = app.spawn_agent((Sequence, tasks![mock_operator("a"), mock_operator("b")]));
+    app.step(1).assert_plan(agent, &["b"]);
+    app.step(1).assert_operators_ran(&["a", "b"]);
+}
+
+#[test]
+fn scripted_operator_follows_script() {
+    let mut app = test_app();
+    let agent = app.spawn_agent((
+        Select,
+        tasks![
+            (
+                conditions![Condition::eq("gave_up", false)],
+                scripted_operator("try", [OperatorStatus::Ongoing, OperatorStatus::Failure]),
+                effects![Effect::set("gave_up", true)],
+            ),
+            mock_operator("give_up"),
+        ],
+    ));
+    app.step(1).assert_plan(agent, &["try"]);
+    app.step(1)
+        .assert_plan(agent, &[])
+        .assert_prop(agent, "gave_up", false);
+    app.step(1).assert_operators_ran(&["try", "try", "try"]);
+}
+
+#[test]
+fn props_are_asserted_after_execution() {
+    let mut app = test_app();
+    let agent = app.spawn_agent((mock_operator("a"), effects![Effect::set("done", true)]));
+    app.step(1)
+        .assert_prop(agent, "done", true)
+        .assert_operators_ran(&["a"]);
+}
+
+#[test]
+fn asserting_props_does_not_insert_them() {
+    let mut app = test_app();
+    let agent = app.world_mut().spawn(Props::default()).id();
+    app.assert_prop(agent, "missing", false);
+    assert!(
+        app.world()
+            .get::<Props>(agent)
+            .unwrap()
+            .iter()
+            .next()
+            .is_none()
+    );
+}